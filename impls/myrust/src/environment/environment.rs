@@ -1,15 +1,25 @@
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
+use crate::read::{LexicalAddress, Symbol};
+
 use super::{EnvironmentEntry, SharedEnvironment};
 
 pub struct Environment {
-    shared_definitions: HashMap<String, Rc<EnvironmentEntry>>,
+    // the root environment holds the globals, looked up by name.
+    // the other ones (fn* calls, let*, catch*) are small, and hold their variables in slots
+    // that the resolver can compute ahead of time.
+    shared_definitions: HashMap<Symbol, Rc<EnvironmentEntry>>,
+    slots: Vec<Rc<EnvironmentEntry>>,
     parent: Option<SharedEnvironment>,
 }
 
 impl Environment {
-    pub fn find(&self, name: &String) -> Option<Rc<EnvironmentEntry>> {
-        match self.shared_definitions.get(name).cloned() {
+    pub fn find(&self, name: Symbol) -> Option<Rc<EnvironmentEntry>> {
+        let local = match self.parent {
+            None => self.shared_definitions.get(&name),
+            Some(_) => self.slots.iter().find(|entry| entry.name() == name),
+        };
+        match local.cloned() {
             Some(val) => Some(val),
             None => match &self.parent {
                 Some(parent) => parent.clone().borrow().find(name),
@@ -17,9 +27,27 @@ impl Environment {
             },
         }
     }
+    // the variable at the given address, if it's still the one with that name.
+    // code built at runtime (e.g. by macros) can be evaluated in other environments than
+    // the one it was resolved for, the caller then falls back to find.
+    pub fn find_at(&self, address: LexicalAddress, name: Symbol) -> Option<Rc<EnvironmentEntry>> {
+        if address.depth == 0 {
+            return self
+                .slots
+                .get(address.slot)
+                .filter(|entry| entry.name() == name)
+                .cloned();
+        }
+        let address = LexicalAddress {
+            depth: address.depth - 1,
+            slot: address.slot,
+        };
+        self.parent.as_ref()?.borrow().find_at(address, name)
+    }
     pub fn new_root() -> Environment {
         Self {
             shared_definitions: HashMap::new(),
+            slots: vec![],
             parent: None,
         }
     }
@@ -29,24 +57,48 @@ impl Environment {
     pub fn new_child(parent: SharedEnvironment) -> Environment {
         Self {
             shared_definitions: HashMap::new(),
+            slots: vec![],
             parent: Some(parent),
         }
     }
     pub fn as_shared(self) -> SharedEnvironment {
         SharedEnvironment(Rc::new(RefCell::new(self)))
     }
+    // redefining a variable keeps its slot, the resolver relies on it
     pub fn set(&mut self, entry: Rc<EnvironmentEntry>) {
-        self.shared_definitions.insert(entry.name().clone(), entry);
+        if self.parent.is_none() {
+            self.shared_definitions.insert(entry.name(), entry);
+        } else if let Some(slot) = self
+            .slots
+            .iter_mut()
+            .find(|slot| slot.name() == entry.name())
+        {
+            *slot = entry;
+        } else {
+            self.slots.push(entry);
+        }
     }
     pub fn set_owned(&mut self, entry: EnvironmentEntry) {
         self.set(Rc::new(entry))
     }
     pub fn get_keys(&self) -> Vec<String> {
+        let keys: Vec<String> = match self.parent {
+            None => self
+                .shared_definitions
+                .keys()
+                .map(|name| name.to_string())
+                .collect(),
+            Some(_) => self
+                .slots
+                .iter()
+                .map(|entry| entry.name().to_string())
+                .collect(),
+        };
         match self.parent {
-            None => self.shared_definitions.keys().cloned().collect(),
+            None => keys,
             Some(ref p) => {
                 let mut parent_vector = p.borrow().get_keys();
-                parent_vector.append(&mut self.shared_definitions.keys().cloned().collect());
+                parent_vector.extend(keys);
                 return parent_vector;
             }
         }
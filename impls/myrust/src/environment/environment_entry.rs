@@ -1,34 +1,37 @@
 use std::{fmt::Display, rc::Rc};
 
-use crate::{functions::NativeFunction, read::AstNode};
+use crate::{
+    functions::NativeFunction,
+    read::{AstNode, Symbol},
+};
 
 use super::EnvironmentEntryValue;
 
 #[derive(PartialEq)]
 pub struct EnvironmentEntry {
-    name: String,
+    name: Symbol,
     value: EnvironmentEntryValue,
 }
 impl EnvironmentEntry {
     pub fn to_ast_node(self: Rc<Self>) -> AstNode {
         match &self.value {
             EnvironmentEntryValue::Value(node) => node.clone(),
-            EnvironmentEntryValue::NativeFunction(_) => AstNode::FunctionPtr(self.clone()),
+            EnvironmentEntryValue::NativeFunction(_) => AstNode::FunctionPtr(self.clone(), None),
         }
     }
     pub fn value(&self) -> &EnvironmentEntryValue {
         &self.value
     }
-    pub fn name(&self) -> &String {
-        &self.name
+    pub fn name(&self) -> Symbol {
+        self.name
     }
     pub fn new_native(func: Rc<dyn NativeFunction>) -> Self {
         Self {
-            name: func.name(),
+            name: Symbol::new(&func.name()),
             value: EnvironmentEntryValue::NativeFunction(func),
         }
     }
-    pub fn new_ast_value(name: String, val: AstNode) -> Self {
+    pub fn new_ast_value(name: Symbol, val: AstNode) -> Self {
         Self {
             name,
             value: EnvironmentEntryValue::Value(val),
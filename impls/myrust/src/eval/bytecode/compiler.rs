@@ -0,0 +1,550 @@
+use std::rc::Rc;
+
+use im_rc::Vector;
+
+use crate::read::{AstNode, SourceSpan, Symbol};
+
+use super::{CallSite, Op, Prototype, Variable};
+use crate::eval::{EnvironmentEntryValue, EvalError, Evaluator, SharedEnvironment};
+
+// compiles a top level form, with the macros and natives of the environment it will run in.
+// the span is used for the errors of forms without a position, like the ones built by natives.
+pub(super) fn compile(
+    evaluator: &Evaluator,
+    ast: &AstNode,
+    env: &SharedEnvironment,
+    span: Option<Rc<SourceSpan>>,
+) -> Rc<Prototype> {
+    let mut compiler = Compiler {
+        evaluator,
+        env: env.clone(),
+        functions: vec![Function::new("top level".to_string(), vec![], ast.clone())],
+        span,
+        name: None,
+    };
+    compiler.compile(ast, true);
+    compiler.emit(Op::Return);
+    Rc::new(compiler.functions.pop().unwrap().proto)
+}
+
+// a function being compiled
+struct Function {
+    proto: Prototype,
+    // the variables in scope, innermost last
+    locals: Vec<Local>,
+    // where the variables of the innermost let* start
+    scope_start: usize,
+}
+
+struct Local {
+    name: Symbol,
+    slot: u32,
+    // declared by the let* being compiled, but not bound yet. the functions it creates can use it,
+    // like they would see the variables set later in its environment.
+    pending: bool,
+}
+
+impl Function {
+    fn new(name: String, params: Vec<Symbol>, body: AstNode) -> Function {
+        Function {
+            proto: Prototype {
+                name,
+                params,
+                body: Rc::new(body),
+                arity: 0,
+                variadic: false,
+                slots: 0,
+                code: vec![],
+                spans: vec![],
+                constants: vec![],
+                globals: vec![],
+                prototypes: vec![],
+                upvalues: vec![],
+                sites: vec![],
+                slot_names: vec![],
+            },
+            locals: vec![],
+            scope_start: 0,
+        }
+    }
+
+    fn local(&self, name: Symbol, with_pending: bool) -> Option<u32> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|local| local.name == name && (with_pending || !local.pending))
+            .map(|local| local.slot)
+    }
+
+    fn new_slot(&mut self, name: Symbol) -> u32 {
+        self.proto.slot_names.push(name);
+        self.proto.slots += 1;
+        (self.proto.slots - 1) as u32
+    }
+
+    fn declare(&mut self, name: Symbol, pending: bool) -> u32 {
+        let slot = self.new_slot(name);
+        self.locals.push(Local {
+            name,
+            slot,
+            pending,
+        });
+        slot
+    }
+
+    fn local_in_scope(&mut self, name: Symbol) -> Option<&mut Local> {
+        self.locals[self.scope_start..]
+            .iter_mut()
+            .find(|local| local.name == name)
+    }
+
+    // binding a name again in the same let* reuses its slot, like setting it again in an environment
+    fn bind(&mut self, name: Symbol) -> u32 {
+        let local = self.local_in_scope(name).unwrap();
+        local.pending = false;
+        local.slot
+    }
+
+    fn capture(&mut self, name: Symbol, from: Variable) -> u32 {
+        let upvalues = &mut self.proto.upvalues;
+        match upvalues.iter().position(|(_, existing)| *existing == from) {
+            Some(index) => index as u32,
+            None => {
+                upvalues.push((name, from));
+                (upvalues.len() - 1) as u32
+            }
+        }
+    }
+}
+
+struct Compiler<'a> {
+    evaluator: &'a Evaluator,
+    // where macros and natives are looked up
+    env: SharedEnvironment,
+    // the function being compiled is the last one, the enclosing ones are before it
+    functions: Vec<Function>,
+    // the position of the innermost form read by the parser
+    span: Option<Rc<SourceSpan>>,
+    // the name of the global a fn* is defined as, for the disassembler
+    name: Option<Symbol>,
+}
+
+impl Compiler<'_> {
+    fn function(&mut self) -> &mut Function {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let span = self.span.clone();
+        let proto = &mut self.function().proto;
+        proto.code.push(op);
+        proto.spans.push(span);
+        proto.code.len() - 1
+    }
+
+    // the position of the next instruction
+    fn here(&mut self) -> u32 {
+        self.function().proto.code.len() as u32
+    }
+
+    fn patch(&mut self, at: usize, target: u32) {
+        let op = &mut self.function().proto.code[at];
+        *op = match *op {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::ExpandMacro { site, .. } => Op::ExpandMacro { site, skip: target },
+            Op::MacroGuard { constant, .. } => Op::MacroGuard {
+                constant,
+                otherwise: target,
+            },
+            Op::PushHandler { slot, .. } => Op::PushHandler { slot, target },
+            op => unreachable!("{:?} has no target", op),
+        };
+    }
+
+    fn add_constant(&mut self, value: AstNode) -> u32 {
+        let constants = &mut self.function().proto.constants;
+        constants.push(value);
+        (constants.len() - 1) as u32
+    }
+
+    fn constant(&mut self, value: AstNode) {
+        let index = self.add_constant(value);
+        self.emit(Op::Constant(index));
+    }
+
+    fn global(&mut self, name: Symbol) -> u32 {
+        let globals = &mut self.function().proto.globals;
+        match globals.iter().position(|global| *global == name) {
+            Some(index) => index as u32,
+            None => {
+                globals.push(name);
+                (globals.len() - 1) as u32
+            }
+        }
+    }
+
+    // locals of the enclosing functions are captured by all the functions in between
+    fn resolve(&mut self, depth: usize, name: Symbol) -> Option<Variable> {
+        let with_pending = depth + 1 < self.functions.len();
+        if let Some(slot) = self.functions[depth].local(name, with_pending) {
+            return Some(Variable::Local(slot));
+        }
+        if depth == 0 {
+            return None;
+        }
+        let from = self.resolve(depth - 1, name)?;
+        Some(Variable::Upvalue(self.functions[depth].capture(name, from)))
+    }
+
+    fn variable(&mut self, name: Symbol) -> Option<Variable> {
+        self.resolve(self.functions.len() - 1, name)
+    }
+
+    // all the variables in scope, for the code evaluated at runtime by a call site
+    fn variables(&mut self) -> Vec<(Symbol, Variable)> {
+        let mut names: Vec<Symbol> = vec![];
+        for function in &self.functions {
+            for local in &function.locals {
+                if !names.contains(&local.name) {
+                    names.push(local.name);
+                }
+            }
+        }
+        names
+            .into_iter()
+            .filter_map(|name| Some((name, self.variable(name)?)))
+            .collect()
+    }
+
+    fn site(&mut self, elements: &Vector<AstNode>, tail: bool, with_variables: bool) -> u32 {
+        let variables = match with_variables {
+            true => self.variables(),
+            false => vec![],
+        };
+        let site = CallSite {
+            form: AstNode::List(elements.clone(), self.span.clone(), None),
+            span: self.span.clone(),
+            argc: elements.len() - 1,
+            tail,
+            variables,
+        };
+        let sites = &mut self.function().proto.sites;
+        sites.push(site);
+        (sites.len() - 1) as u32
+    }
+
+    // `tail` is set for the forms whose value is returned by the function, where calls are tail calls
+    fn compile(&mut self, ast: &AstNode, tail: bool) {
+        match ast {
+            AstNode::UnresolvedSymbol(name, _) => {
+                let op = match self.variable(*name) {
+                    Some(Variable::Local(slot)) => Op::GetLocal(slot),
+                    Some(Variable::Upvalue(index)) => Op::GetUpvalue(index),
+                    None => Op::GetGlobal(self.global(*name)),
+                };
+                self.emit(op);
+            }
+            AstNode::List(elements, span, _) if !elements.is_empty() => {
+                // forms built at runtime don't have a position, the enclosing one is used
+                let outer = self.span.clone();
+                if span.is_some() {
+                    self.span = span.clone();
+                }
+                self.compile_form(elements, tail);
+                self.span = outer;
+            }
+            AstNode::Vector(elements, _) => {
+                for element in elements {
+                    self.compile(element, false);
+                }
+                self.emit(Op::MakeVector(elements.len() as u32));
+            }
+            AstNode::HashMap(hm, _) => {
+                for (key, value) in hm {
+                    self.compile(&key.to_ast_node(), false);
+                    self.compile(value, false);
+                }
+                self.emit(Op::MakeMap(hm.len() as u32));
+            }
+            AstNode::Set(set, _) => {
+                for element in set {
+                    self.compile(&element.to_ast_node(), false);
+                }
+                self.emit(Op::MakeSet(set.len() as u32));
+            }
+            // everything else, including the empty list, evaluates to itself
+            any => self.constant(any.clone()),
+        }
+    }
+
+    // special forms are only recognized if their name isn't a local variable.
+    // malformed ones are left to the natives, to get the same errors.
+    fn compile_form(&mut self, elements: &Vector<AstNode>, tail: bool) {
+        let head = match elements.front() {
+            Some(AstNode::UnresolvedSymbol(name, _)) if self.variable(*name).is_none() => {
+                Some(*name)
+            }
+            _ => None,
+        };
+        match (head.map(|name| name.name()), elements.len()) {
+            (Some("quote"), 2) => self.constant(elements[1].clone()),
+            (Some("if"), 3 | 4) => self.compile_if(elements, tail),
+            (Some("do"), len) if len > 1 => {
+                for element in elements.iter().skip(1).take(len - 2) {
+                    self.compile(element, false);
+                    self.emit(Op::Pop);
+                }
+                self.compile(&elements[len - 1], tail);
+            }
+            (Some("def!"), 3) => match &elements[1] {
+                AstNode::UnresolvedSymbol(name, _) => {
+                    self.name = Some(*name);
+                    self.compile(&elements[2], false);
+                    self.name = None;
+                    let index = self.global(*name);
+                    self.emit(Op::DefGlobal(index));
+                }
+                _ => self.compile_special(elements),
+            },
+            (Some("let*"), 3) if self.compile_let(elements, tail) => {}
+            (Some("fn*"), 3) if self.compile_fn(elements) => {}
+            (Some("try*"), 3) if self.compile_try(elements, tail) => {}
+            _ => match head.and_then(|name| self.env.borrow().find(name)) {
+                Some(entry) => match entry.value() {
+                    EnvironmentEntryValue::NativeFunction(func) if !func.evaluates_arguments() => {
+                        self.compile_special(elements)
+                    }
+                    EnvironmentEntryValue::Value(macro_fn @ AstNode::Lambda(lambda, _))
+                        if lambda.is_macro =>
+                    {
+                        // compiling the expansion counts as a nested evaluation, like running it.
+                        // a call expanding too deep fails when it runs, instead of being expanded
+                        // again by the vm.
+                        let depth = self.evaluator.depth();
+                        let args = elements.iter().skip(1).cloned().collect();
+                        match self
+                            .evaluator
+                            .enter_eval()
+                            .and_then(|_| self.evaluator.expand_macro(lambda, args))
+                            .map_err(EvalError::into_inner)
+                        {
+                            Ok(expansion) => {
+                                self.compile_expansion(elements, macro_fn.clone(), &expansion, tail)
+                            }
+                            Err(EvalError::StackOverflow(_)) => {
+                                self.emit(Op::StackOverflow);
+                            }
+                            // fails the same way when it runs
+                            Err(_) => self.compile_call(elements, tail, true),
+                        }
+                        self.evaluator.restore_depth(depth);
+                    }
+                    _ => self.compile_call(elements, tail, false),
+                },
+                // might be a macro defined by the time the call runs
+                None => self.compile_call(elements, tail, head.is_some()),
+            },
+        }
+    }
+
+    // the expansion is used as long as the macro isn't redefined, the call is expanded again otherwise
+    fn compile_expansion(
+        &mut self,
+        elements: &Vector<AstNode>,
+        macro_fn: AstNode,
+        expansion: &AstNode,
+        tail: bool,
+    ) {
+        // the macro is on the call stack while its expansion runs, like with the tree walker
+        let site = self.site(elements, tail, false);
+        self.compile(&elements[0], false);
+        self.emit(Op::Callee(site));
+        let constant = self.add_constant(macro_fn);
+        let guard = self.emit(Op::MacroGuard {
+            constant,
+            otherwise: 0,
+        });
+        self.compile(expansion, tail);
+        self.emit(Op::EndExpansion);
+        let jump_to_end = self.emit(Op::Jump(0));
+        let here = self.here();
+        self.patch(guard, here);
+        self.compile_call(elements, tail, true);
+        let here = self.here();
+        self.patch(jump_to_end, here);
+    }
+
+    fn compile_if(&mut self, elements: &Vector<AstNode>, tail: bool) {
+        self.compile(&elements[1], false);
+        let jump_to_else = self.emit(Op::JumpIfFalse(0));
+        self.compile(&elements[2], tail);
+        let jump_to_end = self.emit(Op::Jump(0));
+        let here = self.here();
+        self.patch(jump_to_else, here);
+        match elements.get(3) {
+            Some(otherwise) => self.compile(otherwise, tail),
+            None => self.constant(AstNode::Nil),
+        }
+        let here = self.here();
+        self.patch(jump_to_end, here);
+    }
+
+    fn compile_let(&mut self, elements: &Vector<AstNode>, tail: bool) -> bool {
+        let bindings = match &elements[1] {
+            AstNode::List(bindings, _, _) | AstNode::Vector(bindings, _) => bindings,
+            _ => return false,
+        };
+        let valid = bindings.len() % 2 == 0
+            && bindings
+                .iter()
+                .step_by(2)
+                .all(|name| matches!(name, AstNode::UnresolvedSymbol(_, _)));
+        if !valid {
+            return false;
+        }
+
+        let names: Vec<Symbol> = bindings
+            .iter()
+            .step_by(2)
+            .map(|name| name.clone().try_unwrap_symbol().unwrap())
+            .collect();
+        let function = self.function();
+        let (locals, scope_start) = (function.locals.len(), function.scope_start);
+        function.scope_start = locals;
+        for name in &names {
+            if function.local_in_scope(*name).is_none() {
+                function.declare(*name, true);
+            }
+        }
+        // each value sees the variables bound before it
+        for (name, value) in names.iter().zip(bindings.iter().skip(1).step_by(2)) {
+            self.compile(value, false);
+            let slot = self.function().bind(*name);
+            self.emit(Op::SetLocal(slot));
+        }
+        self.compile(&elements[2], tail);
+        let function = self.function();
+        function.locals.truncate(locals);
+        function.scope_start = scope_start;
+        true
+    }
+
+    fn compile_fn(&mut self, elements: &Vector<AstNode>) -> bool {
+        let params = match &elements[1] {
+            AstNode::List(params, _, _) | AstNode::Vector(params, _) => params,
+            _ => return false,
+        };
+        let mut names = vec![];
+        for param in params {
+            match param {
+                AstNode::UnresolvedSymbol(name, _) => names.push(*name),
+                _ => return false,
+            }
+        }
+        // & must be followed by exactly one name, calls fail otherwise
+        let variadic = match names.iter().position(|name| name.name() == "&") {
+            Some(position) if position + 2 == names.len() => true,
+            Some(_) => return false,
+            None => false,
+        };
+
+        let name = match self.name.take() {
+            Some(name) => name.to_string(),
+            None => "fn*".to_string(),
+        };
+        let mut function = Function::new(name, names.clone(), elements[2].clone());
+        // the parameters are in the first slots, in order
+        for name in names.iter().filter(|name| name.name() != "&") {
+            function.declare(*name, false);
+        }
+        function.proto.arity = names.len() - 2 * variadic as usize;
+        function.proto.variadic = variadic;
+        self.functions.push(function);
+        self.compile(&elements[2], true);
+        self.emit(Op::Return);
+        let proto = self.functions.pop().unwrap().proto;
+
+        let prototypes = &mut self.function().proto.prototypes;
+        prototypes.push(Rc::new(proto));
+        let index = (prototypes.len() - 1) as u32;
+        self.emit(Op::Closure(index));
+        true
+    }
+
+    fn compile_try(&mut self, elements: &Vector<AstNode>, tail: bool) -> bool {
+        let clause = match &elements[2] {
+            AstNode::List(clause, _, _) | AstNode::Vector(clause, _) if clause.len() == 3 => clause,
+            _ => return false,
+        };
+        let name = match (&clause[0], &clause[1]) {
+            (AstNode::UnresolvedSymbol(catch, _), AstNode::UnresolvedSymbol(name, _))
+                if catch.name() == "catch*" =>
+            {
+                *name
+            }
+            _ => return false,
+        };
+
+        // the handler gets the exception in a new slot, and the stack trace in the next one
+        let stack_trace = Symbol::new("*stack-trace*");
+        let function = self.function();
+        let (locals, scope_start) = (function.locals.len(), function.scope_start);
+        let slot = function.new_slot(name);
+        function.new_slot(stack_trace);
+        // try* is on the call stack while the body runs, like when the native runs it
+        let site = self.site(elements, false, false);
+        self.compile(&elements[0], false);
+        self.emit(Op::Callee(site));
+        self.emit(Op::Pop);
+        let push_handler = self.emit(Op::PushHandler { slot, target: 0 });
+        // the handler must stay in place while the body runs, so it isn't in tail position
+        self.compile(&elements[1], false);
+        self.emit(Op::PopHandler);
+        let jump_to_end = self.emit(Op::Jump(0));
+
+        let here = self.here();
+        self.patch(push_handler, here);
+        let function = self.function();
+        function.scope_start = locals;
+        for (name, slot) in [(name, slot), (stack_trace, slot + 1)] {
+            function.locals.push(Local {
+                name,
+                slot,
+                pending: false,
+            });
+        }
+        self.compile(&clause[2], tail);
+        let function = self.function();
+        function.locals.truncate(locals);
+        function.scope_start = scope_start;
+
+        let here = self.here();
+        self.patch(jump_to_end, here);
+        true
+    }
+
+    fn compile_call(&mut self, elements: &Vector<AstNode>, tail: bool, check_macro: bool) {
+        let site = self.site(elements, tail, check_macro);
+        self.compile(&elements[0], false);
+        self.emit(Op::Callee(site));
+        let expand_macro = check_macro.then(|| self.emit(Op::ExpandMacro { site, skip: 0 }));
+        for element in elements.iter().skip(1) {
+            self.compile(element, false);
+        }
+        self.emit(match tail {
+            true => Op::TailCall(site),
+            false => Op::Call(site),
+        });
+        if let Some(at) = expand_macro {
+            let here = self.here();
+            self.patch(at, here);
+        }
+    }
+
+    fn compile_special(&mut self, elements: &Vector<AstNode>) {
+        let site = self.site(elements, false, true);
+        self.compile(&elements[0], false);
+        self.emit(Op::CallSpecial(site));
+    }
+}
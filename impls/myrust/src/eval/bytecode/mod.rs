@@ -0,0 +1,303 @@
+mod compiler;
+mod vm;
+
+use std::{cell::RefCell, fmt::Write, rc::Rc};
+
+use crate::read::{AstNode, SourceSpan, Symbol};
+
+use super::{EvalError, Evaluator, SharedEnvironment};
+
+// the bytecode backend: each top level form is compiled to the code of a function without
+// parameters, and run by a stack machine (vm.rs). the variables of fn*, let* and catch* live in the
+// slots of the frame of their function, closures capture the ones of enclosing functions as
+// upvalues. globals are looked up by name in the environment, natives are called as usual.
+
+// an instruction. the numbers are indexes into the tables of the function, or positions in its code
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Op {
+    Constant(u32),
+    GetLocal(u32),
+    SetLocal(u32), // pops the value
+    GetUpvalue(u32),
+    GetGlobal(u32),
+    DefGlobal(u32), // def!, the value stays on the stack
+    Pop,
+    Jump(u32),
+    JumpIfFalse(u32), // pops the condition
+    Closure(u32),
+    // the function of the call site is on the stack: the call goes on the call stack of the
+    // evaluator while the arguments are evaluated, like with the tree walker
+    Callee(u32),
+    // the function and the arguments of the call site are on the stack
+    Call(u32),
+    TailCall(u32),
+    // the function of the call site is on the stack. if it turns out to be a macro,
+    // the expansion is evaluated instead of the call, which is skipped
+    ExpandMacro { site: u32, skip: u32 },
+    // pops the function of a macro call expanded when compiling, on the call stack since Callee.
+    // if it isn't the macro that was expanded (a constant) anymore, the call leaves the call
+    // stack and this jumps to the code making it instead
+    MacroGuard { constant: u32, otherwise: u32 },
+    // the expansion of a macro call is done, the call leaves the call stack
+    EndExpansion,
+    // a native that gets its arguments unevaluated, like quasiquote or defmacro!
+    CallSpecial(u32),
+    MakeVector(u32),
+    MakeMap(u32), // the number of key/value pairs
+    MakeSet(u32),
+    // try*: errors go to target, with the exception in slot and the stack trace in the next one
+    PushHandler { slot: u32, target: u32 },
+    PopHandler,
+    // a macro call nested too deep to be expanded when compiling
+    StackOverflow,
+    Return,
+}
+
+impl Op {
+    // the instructions evaluating a form: atoms, calls, fn*, if, def! and collections. each one is
+    // a step of the budget, like each form evaluated by the other backends.
+    fn is_step(&self) -> bool {
+        matches!(
+            self,
+            Op::Constant(_)
+                | Op::GetLocal(_)
+                | Op::GetUpvalue(_)
+                | Op::GetGlobal(_)
+                | Op::DefGlobal(_)
+                | Op::JumpIfFalse(_)
+                | Op::Closure(_)
+                | Op::Callee(_)
+                | Op::CallSpecial(_)
+                | Op::MakeVector(_)
+                | Op::MakeMap(_)
+                | Op::MakeSet(_)
+        )
+    }
+}
+
+// where a function finds a variable of an enclosing one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Variable {
+    Local(u32),
+    Upvalue(u32),
+}
+
+pub(super) struct CallSite {
+    form: AstNode,
+    span: Option<Rc<SourceSpan>>,
+    argc: usize,
+    tail: bool,
+    // the variables around the call, for the code that natives and macros give back to evaluate.
+    // only known for the calls that might need them.
+    variables: Vec<(Symbol, Variable)>,
+}
+
+// a compiled fn*, or top level form
+pub(super) struct Prototype {
+    name: String,
+    params: Vec<Symbol>,
+    body: Rc<AstNode>,
+    arity: usize, // the parameters before &
+    variadic: bool,
+    slots: usize,
+    code: Vec<Op>,
+    // the position of the innermost form of each instruction, for the errors
+    spans: Vec<Option<Rc<SourceSpan>>>,
+    constants: Vec<AstNode>,
+    globals: Vec<Symbol>,
+    prototypes: Vec<Rc<Prototype>>,
+    upvalues: Vec<(Symbol, Variable)>,
+    sites: Vec<CallSite>,
+    slot_names: Vec<Symbol>,
+}
+
+pub(super) enum Upvalue {
+    // the variable is still in the slot of a running function, at this position of the stack
+    Open(usize),
+    Closed(AstNode),
+}
+
+// a function created by running a fn*, with the variables it captured
+pub struct Closure {
+    proto: Rc<Prototype>,
+    upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // where globals are looked up
+    env: SharedEnvironment,
+}
+
+impl Evaluator {
+    pub(super) fn eval_bytecode(
+        &self,
+        ast: &AstNode,
+        env: SharedEnvironment,
+    ) -> Result<AstNode, EvalError> {
+        // the forms of a top level do are compiled one at a time,
+        // so that the macros defined by one can be used by the next ones (e.g. with load-file)
+        if let Some(forms) = top_level_forms(ast) {
+            let mut value = AstNode::Nil;
+            for form in forms {
+                value = self.eval_bytecode(form, env.clone())?;
+            }
+            return Ok(value);
+        }
+        let proto = compiler::compile(self, ast, &env, None);
+        self.run_closure(Rc::new(Closure::new(proto, env)), vec![])
+    }
+
+    pub(super) fn run_closure(
+        &self,
+        closure: Rc<Closure>,
+        args: Vec<AstNode>,
+    ) -> Result<AstNode, EvalError> {
+        vm::run(self, closure, args)
+    }
+}
+
+fn top_level_forms(ast: &AstNode) -> Option<impl Iterator<Item = &AstNode>> {
+    match ast {
+        AstNode::List(elements, _, _) if elements.len() > 1 => match &elements[0] {
+            AstNode::UnresolvedSymbol(name, _) if name.name() == "do" => {
+                Some(elements.iter().skip(1))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl Closure {
+    fn new(proto: Rc<Prototype>, env: SharedEnvironment) -> Closure {
+        Closure {
+            proto,
+            upvalues: vec![],
+            env,
+        }
+    }
+
+    // the code of the function and of the ones it creates, for (disassemble f)
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+        self.proto.disassemble(&mut out);
+        out
+    }
+}
+
+impl Prototype {
+    fn disassemble(&self, out: &mut String) {
+        let params: Vec<&str> = self.params.iter().map(|param| param.name()).collect();
+        writeln!(out, "== {} ({}) ==", self.name, params.join(" ")).unwrap();
+        writeln!(
+            out,
+            "slots: {}, upvalues: {}",
+            self.slots,
+            self.upvalues.len()
+        )
+        .unwrap();
+        for (ip, op) in self.code.iter().enumerate() {
+            let (name, operands, comment) = self.describe(*op);
+            let line = format!("{:>4}  {:<12} {}", ip, name, operands);
+            match comment {
+                Some(comment) => writeln!(out, "{:<30} ; {}", line, comment),
+                None => writeln!(out, "{}", line.trim_end()),
+            }
+            .unwrap();
+        }
+        for proto in &self.prototypes {
+            writeln!(out).unwrap();
+            proto.disassemble(out);
+        }
+    }
+
+    fn describe(&self, op: Op) -> (&'static str, String, Option<String>) {
+        let site = |index: u32| {
+            let site = &self.sites[index as usize];
+            let form = format!("{}", site.form);
+            Some(match form.char_indices().nth(40) {
+                Some((end, _)) => format!("{}...", &form[..end]),
+                None => form,
+            })
+        };
+        let variable = |variable: Variable| match variable {
+            Variable::Local(slot) => format!("local {}", slot),
+            Variable::Upvalue(index) => format!("upvalue {}", index),
+        };
+        match op {
+            Op::Constant(index) => (
+                "Constant",
+                index.to_string(),
+                Some(format!("{}", self.constants[index as usize])),
+            ),
+            Op::GetLocal(slot) => (
+                "GetLocal",
+                slot.to_string(),
+                Some(self.slot_names[slot as usize].to_string()),
+            ),
+            Op::SetLocal(slot) => (
+                "SetLocal",
+                slot.to_string(),
+                Some(self.slot_names[slot as usize].to_string()),
+            ),
+            Op::GetUpvalue(index) => (
+                "GetUpvalue",
+                index.to_string(),
+                Some(self.upvalues[index as usize].0.to_string()),
+            ),
+            Op::GetGlobal(index) => (
+                "GetGlobal",
+                index.to_string(),
+                Some(self.globals[index as usize].to_string()),
+            ),
+            Op::DefGlobal(index) => (
+                "DefGlobal",
+                index.to_string(),
+                Some(self.globals[index as usize].to_string()),
+            ),
+            Op::Pop => ("Pop", String::new(), None),
+            Op::Jump(target) => ("Jump", target.to_string(), None),
+            Op::JumpIfFalse(target) => ("JumpIfFalse", target.to_string(), None),
+            Op::Closure(index) => {
+                let proto = &self.prototypes[index as usize];
+                let captures: Vec<String> = proto
+                    .upvalues
+                    .iter()
+                    .map(|(name, from)| format!("{} from {}", name, variable(*from)))
+                    .collect();
+                (
+                    "Closure",
+                    index.to_string(),
+                    Some(match captures.is_empty() {
+                        true => proto.name.clone(),
+                        false => format!("{}, capturing {}", proto.name, captures.join(", ")),
+                    }),
+                )
+            }
+            Op::Callee(index) => ("Callee", index.to_string(), site(index)),
+            Op::Call(index) => ("Call", index.to_string(), site(index)),
+            Op::TailCall(index) => ("TailCall", index.to_string(), site(index)),
+            Op::ExpandMacro { site: index, skip } => {
+                ("ExpandMacro", format!("{} {}", index, skip), site(index))
+            }
+            Op::MacroGuard {
+                constant,
+                otherwise,
+            } => (
+                "MacroGuard",
+                format!("{} {}", constant, otherwise),
+                Some(format!("{}", self.constants[constant as usize])),
+            ),
+            Op::CallSpecial(index) => ("CallSpecial", index.to_string(), site(index)),
+            Op::MakeVector(count) => ("MakeVector", count.to_string(), None),
+            Op::MakeMap(count) => ("MakeMap", count.to_string(), None),
+            Op::MakeSet(count) => ("MakeSet", count.to_string(), None),
+            Op::PushHandler { slot, target } => (
+                "PushHandler",
+                format!("{} {}", slot, target),
+                Some(self.slot_names[slot as usize].to_string()),
+            ),
+            Op::PopHandler => ("PopHandler", String::new(), None),
+            Op::EndExpansion => ("EndExpansion", String::new(), None),
+            Op::StackOverflow => ("StackOverflow", String::new(), None),
+            Op::Return => ("Return", String::new(), None),
+        }
+    }
+}
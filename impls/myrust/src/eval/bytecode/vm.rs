@@ -0,0 +1,620 @@
+use std::{cell::RefCell, rc::Rc};
+
+use im_rc::{HashMap, HashSet};
+
+use crate::{
+    functions::FunctionCallResultSuccess,
+    read::{AstNode, LambdaEntry, SourceSpan},
+};
+
+use super::{compiler::compile, top_level_forms, CallSite, Closure, Op, Upvalue, Variable};
+use crate::eval::{
+    bind_params, CallFrame, CompiledBody, Environment, EnvironmentEntry, EnvironmentEntryValue,
+    EvalError, Evaluator, SharedEnvironment, TraceFlag,
+};
+
+// a running function. its function value is just below base, then come its slots and temporaries.
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    base: usize,
+    // where the frame starts in the call stack of the evaluator, and where its next call goes
+    stack_depth: usize,
+    depth: usize,
+}
+
+// a try* waiting for errors
+struct Handler {
+    frames: usize,
+    stack_len: usize,
+    depth: usize,
+    slot: usize,
+    target: usize,
+}
+
+struct Vm<'a> {
+    evaluator: &'a Evaluator,
+    // shared by the vms running inside each other, see lend_stack
+    stack: Vec<AstNode>,
+    frames: Vec<Frame>,
+    // the upvalues still pointing into the stack
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    handlers: Vec<Handler>,
+    // the depth of evaluation when the vm started, each frame counts as one more
+    depth: usize,
+}
+
+pub(super) fn run(
+    evaluator: &Evaluator,
+    closure: Rc<Closure>,
+    args: Vec<AstNode>,
+) -> Result<AstNode, EvalError> {
+    let stack_depth = evaluator.call_stack_len();
+    let mut stack = evaluator.take_vm_stack();
+    let start = stack.len();
+    stack.push(AstNode::Nil);
+    let mut vm = Vm {
+        evaluator,
+        stack,
+        frames: vec![],
+        open_upvalues: vec![],
+        handlers: vec![],
+        depth: evaluator.depth(),
+    };
+    let argc = args.len();
+    vm.stack.extend(args);
+    let result = vm
+        .enter(closure, argc, stack_depth)
+        .and_then(|()| vm.execute());
+    evaluator.truncate_call_stack(stack_depth);
+    // after an error, closures might still point into the frames left
+    vm.close_upvalues(start);
+    vm.stack.truncate(start);
+    evaluator.put_vm_stack(vm.stack);
+    evaluator.restore_depth(vm.depth);
+    result
+}
+
+fn is_false(value: &AstNode) -> bool {
+    matches!(value, AstNode::Bool(false) | AstNode::Nil)
+}
+
+fn unevaluated_args(site: &CallSite) -> Vec<AstNode> {
+    match &site.form {
+        AstNode::List(elements, _, _) => elements.iter().skip(1).cloned().collect(),
+        _ => unreachable!(),
+    }
+}
+
+impl Vm<'_> {
+    fn frame(&self) -> &Frame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn pop(&mut self) -> AstNode {
+        self.stack.pop().unwrap()
+    }
+
+    // runs code that might call closures, with the stack given back to the evaluator meanwhile.
+    // the vms running them push their frames on top of it, where the open upvalues of the
+    // closures are still valid.
+    fn lend_stack<T>(&mut self, f: impl FnOnce(&Evaluator) -> T) -> T {
+        self.evaluator.put_vm_stack(std::mem::take(&mut self.stack));
+        let result = f(self.evaluator);
+        self.stack = self.evaluator.take_vm_stack();
+        result
+    }
+
+    // runs until the first frame returns. errors go to the innermost try*, if there's one.
+    fn execute(&mut self) -> Result<AstNode, EvalError> {
+        loop {
+            match self.run() {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    let frame = self.frame();
+                    let span = &frame.closure.proto.spans[frame.ip - 1];
+                    let err = self.evaluator.traced(err, span.as_ref());
+                    self.catch(err)?;
+                }
+            }
+        }
+    }
+
+    fn run(&mut self) -> Result<AstNode, EvalError> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let op = frame.closure.proto.code[frame.ip];
+            frame.ip += 1;
+            let base = frame.base;
+            if op.is_step() {
+                self.evaluator.step()?;
+            }
+            match op {
+                Op::Constant(index) => {
+                    let value = frame.closure.proto.constants[index as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::GetLocal(slot) => {
+                    let value = self.stack[base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    let value = self.pop();
+                    self.stack[base + slot as usize] = value;
+                }
+                Op::GetUpvalue(index) => {
+                    let value = match &*frame.closure.upvalues[index as usize].borrow() {
+                        Upvalue::Open(position) => self.stack[*position].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                Op::GetGlobal(index) => {
+                    let name = frame.closure.proto.globals[index as usize];
+                    let value = frame
+                        .closure
+                        .env
+                        .borrow()
+                        .find(name)
+                        .ok_or_else(|| EvalError::SymbolNotFound(name.to_string()))?
+                        .to_ast_node();
+                    self.stack.push(value);
+                }
+                Op::DefGlobal(index) => {
+                    let name = frame.closure.proto.globals[index as usize];
+                    let value = self.stack.last().unwrap().clone();
+                    frame
+                        .closure
+                        .env
+                        .get_root()
+                        .borrow_mut()
+                        .set_owned(EnvironmentEntry::new_ast_value(name, value));
+                }
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Jump(target) => frame.ip = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if is_false(&self.pop()) {
+                        self.frame_mut().ip = target as usize;
+                    }
+                }
+                Op::Closure(index) => {
+                    let closure = self.closure(index as usize);
+                    self.stack.push(closure);
+                }
+                Op::Callee(site) => self.callee(site as usize),
+                Op::Call(site) | Op::TailCall(site) => self.call(site as usize)?,
+                Op::ExpandMacro { site, skip } => {
+                    if let AstNode::Lambda(lambda, _) = self.stack.last().unwrap() {
+                        if lambda.is_macro {
+                            let lambda = lambda.clone();
+                            self.pop();
+                            let depth = self.end_call();
+                            let value = self.expand(site as usize, &lambda)?;
+                            self.returned(value, depth);
+                            self.frame_mut().ip = skip as usize;
+                        }
+                    }
+                }
+                Op::MacroGuard {
+                    constant,
+                    otherwise,
+                } => {
+                    let expanded = match (
+                        &frame.closure.proto.constants[constant as usize],
+                        self.stack.pop().unwrap(),
+                    ) {
+                        (AstNode::Lambda(expanded, _), AstNode::Lambda(lambda, _)) => {
+                            Rc::ptr_eq(expanded, &lambda)
+                        }
+                        _ => false,
+                    };
+                    if !expanded {
+                        let depth = self.end_call();
+                        self.evaluator.truncate_call_stack(depth);
+                        self.frame_mut().ip = otherwise as usize;
+                    }
+                }
+                Op::CallSpecial(site) => self.call_special(site as usize)?,
+                Op::MakeVector(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack
+                        .push(AstNode::Vector(elements.into_iter().collect(), None));
+                }
+                Op::MakeMap(count) => {
+                    let entries = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                    let mut hm = HashMap::new();
+                    let mut entries = entries.into_iter();
+                    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                        hm.insert(key.try_unwrap_hashmap_key()?, value);
+                    }
+                    self.stack.push(AstNode::HashMap(hm, None));
+                }
+                Op::MakeSet(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count as usize);
+                    let set: HashSet<_> = Result::from_iter(
+                        elements
+                            .into_iter()
+                            .map(|element| element.try_unwrap_hashmap_key()),
+                    )?;
+                    self.stack.push(AstNode::Set(set, None));
+                }
+                Op::PushHandler { slot, target } => {
+                    let depth = frame.depth;
+                    let handler = Handler {
+                        frames: self.frames.len(),
+                        stack_len: self.stack.len(),
+                        depth,
+                        slot: slot as usize,
+                        target: target as usize,
+                    };
+                    self.handlers.push(handler);
+                }
+                Op::PopHandler => {
+                    self.handlers.pop();
+                    let depth = self.end_call();
+                    self.evaluator.truncate_call_stack(depth);
+                }
+                Op::EndExpansion => {
+                    let depth = self.end_call();
+                    self.evaluator.truncate_call_stack(depth);
+                }
+                Op::StackOverflow => {
+                    return Err(EvalError::StackOverflow(self.evaluator.max_depth()))
+                }
+                Op::Return => {
+                    let value = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.frames_dropped();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base - 1);
+                    if self.frames.is_empty() {
+                        return Ok(value);
+                    }
+                    let depth = self.frame().depth;
+                    self.returned(value, depth);
+                }
+            }
+        }
+    }
+
+    // sets up the frame of a call, with the function value and the arguments on top of the stack.
+    // the calls made by the function go at stack_depth in the call stack of the evaluator.
+    fn enter(
+        &mut self,
+        closure: Rc<Closure>,
+        argc: usize,
+        stack_depth: usize,
+    ) -> Result<(), EvalError> {
+        let proto = &closure.proto;
+        let base = self.stack.len() - argc;
+        if argc < proto.arity {
+            return Err(EvalError::custom_exception_str(
+                "Not enough parameters supplied to lambda function call".to_string(),
+            ));
+        }
+        if proto.variadic {
+            let rest = self.stack.split_off(base + proto.arity);
+            self.stack
+                .push(AstNode::List(rest.into_iter().collect(), None, None));
+        } else if argc > proto.arity {
+            return Err(EvalError::custom_exception_str(
+                "Too many parameters passed to lambda call",
+            ));
+        }
+        self.stack.resize(base + proto.slots, AstNode::Nil);
+        if self.evaluator.is_tracing(&TraceFlag::TraceFnCalls) {
+            let params = proto.arity + proto.variadic as usize;
+            let mut env = Environment::new_child(closure.env.clone());
+            for (slot, name) in proto.slot_names.iter().take(params).enumerate() {
+                env.set_owned(EnvironmentEntry::new_ast_value(
+                    *name,
+                    self.stack[base + slot].clone(),
+                ));
+            }
+            self.evaluator
+                .trace_lambda_funcall(&proto.body, &proto.params, &env);
+        }
+        self.evaluator.enter_eval()?;
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            base,
+            stack_depth,
+            depth: stack_depth,
+        });
+        Ok(())
+    }
+
+    // a tail call replaces the frame of the function making it, unless it's the first one
+    fn is_tail(&self, site: &CallSite) -> bool {
+        site.tail && self.frames.len() > 1
+    }
+
+    fn callee(&mut self, site_index: usize) {
+        let frame = self.frame();
+        let site = &frame.closure.proto.sites[site_index];
+        let depth = match self.is_tail(site) {
+            true => frame.stack_depth - 1,
+            false => frame.depth,
+        };
+        self.evaluator.push_call_frame(
+            CallFrame {
+                function: self.stack.last().unwrap().clone(),
+                form: site.form.clone(),
+                span: site.span.clone(),
+            },
+            depth,
+        );
+        // natives go above the frame of the lambda they replace, see push_call_frame
+        self.frame_mut().depth = self.evaluator.call_stack_len();
+    }
+
+    // where the call of the last callee is in the call stack
+    fn end_call(&mut self) -> usize {
+        let frame = self.frame_mut();
+        frame.depth -= 1;
+        frame.depth
+    }
+
+    fn call(&mut self, site_index: usize) -> Result<(), EvalError> {
+        let closure = self.frame().closure.clone();
+        let site = &closure.proto.sites[site_index];
+        let tail = self.is_tail(site);
+        let depth = self.end_call();
+        let function_index = self.stack.len() - site.argc - 1;
+
+        match self.stack[function_index].clone() {
+            AstNode::Lambda(lambda, _) if !lambda.is_macro => match &lambda.code {
+                Some(CompiledBody::Bytecode(callee)) => {
+                    if tail {
+                        self.reuse_frame(function_index);
+                    }
+                    self.enter(callee.clone(), site.argc, depth + 1)
+                }
+                // created by the fn* native, from code built at runtime
+                _ => {
+                    let args = self.stack.split_off(function_index + 1);
+                    self.pop();
+                    let new_env = bind_params(&lambda, args)?;
+                    self.evaluator
+                        .trace_lambda_funcall(&lambda.body, &lambda.params, &new_env);
+                    let value = self.lend_stack(|evaluator| {
+                        evaluator.eval_borrowed(&lambda.body, new_env.as_shared())
+                    })?;
+                    self.returned(value, depth);
+                    Ok(())
+                }
+            },
+            // became a macro after the call was compiled
+            AstNode::Lambda(lambda, _) => {
+                self.stack.truncate(function_index);
+                let value = self.expand(site_index, &lambda)?;
+                self.returned(value, depth);
+                Ok(())
+            }
+            AstNode::FunctionPtr(definition, _) => match definition.value() {
+                EnvironmentEntryValue::NativeFunction(func) => {
+                    let args = self.stack.split_off(function_index + 1);
+                    self.pop();
+                    let env = closure.env.clone();
+                    let result = self.lend_stack(|evaluator| {
+                        func.run_with_params(args, env, evaluator.clone())
+                    });
+                    match result? {
+                        FunctionCallResultSuccess::Value(value) => {
+                            self.returned(value, depth);
+                            Ok(())
+                        }
+                        FunctionCallResultSuccess::TailCall(tailcalldata) => {
+                            let (ast, env) = tailcalldata.destructure();
+                            self.run_form(&ast, env, site.span.clone(), tail, depth)
+                        }
+                    }
+                }
+                EnvironmentEntryValue::Value(_) => unreachable!("This should never be a value, as those get evaluated out into actual AST nodes in the evaluator")
+            },
+            node => Err(EvalError::InvalidFunctionCallNodeType(node)),
+        }
+    }
+
+    // the value of a call that didn't need a frame
+    fn returned(&mut self, value: AstNode, depth: usize) {
+        self.stack.push(value);
+        self.evaluator.truncate_call_stack(depth);
+    }
+
+    // a form given back by a native, like the one of eval
+    fn run_form(
+        &mut self,
+        ast: &AstNode,
+        env: SharedEnvironment,
+        span: Option<Rc<SourceSpan>>,
+        tail: bool,
+        depth: usize,
+    ) -> Result<(), EvalError> {
+        if top_level_forms(ast).is_some() {
+            let value = self.lend_stack(|evaluator| evaluator.eval_borrowed(ast, env))?;
+            self.returned(value, depth);
+            return Ok(());
+        }
+        // compiling might run macros
+        let proto = self.lend_stack(|evaluator| compile(evaluator, ast, &env, span));
+        // in tail position, the tail calls of the form replace the lambda returning it, like in
+        // the eval loop. its other calls go above the native.
+        let stack_depth = match tail {
+            true => self.frame().stack_depth,
+            false => depth + 1,
+        };
+        self.stack.push(AstNode::Nil);
+        if tail {
+            self.reuse_frame(self.stack.len() - 1);
+        }
+        self.enter(Rc::new(Closure::new(proto, env)), 0, stack_depth)?;
+        self.frame_mut().depth = depth + 1;
+        Ok(())
+    }
+
+    // drops the current frame, moving the function and arguments of a tail call in its place
+    fn reuse_frame(&mut self, function_index: usize) {
+        let frame = self.frames.pop().unwrap();
+        self.frames_dropped();
+        self.close_upvalues(frame.base);
+        self.stack.drain(frame.base - 1..function_index);
+    }
+
+    fn frames_dropped(&self) {
+        self.evaluator.restore_depth(self.depth + self.frames.len());
+    }
+
+    // a native getting its arguments unevaluated, with the function on top of the stack
+    fn call_special(&mut self, site_index: usize) -> Result<(), EvalError> {
+        let closure = self.frame().closure.clone();
+        let site = &closure.proto.sites[site_index];
+        let depth = self.frame().depth;
+        let function = self.pop();
+        self.evaluator.push_call_frame(
+            CallFrame {
+                function: function.clone(),
+                form: site.form.clone(),
+                span: site.span.clone(),
+            },
+            depth,
+        );
+        let env = self.variables(&closure, site);
+        let args = unevaluated_args(site);
+        let value = self.lend_stack(|evaluator| match &function {
+            AstNode::FunctionPtr(definition, _) => match definition.value() {
+                EnvironmentEntryValue::NativeFunction(func) => {
+                    match func.run_with_params(args, env, evaluator.clone())? {
+                        FunctionCallResultSuccess::Value(value) => Ok(value),
+                        FunctionCallResultSuccess::TailCall(tailcalldata) => {
+                            let (ast, env) = tailcalldata.destructure();
+                            evaluator.eval_borrowed(&ast, env)
+                        }
+                    }
+                }
+                EnvironmentEntryValue::Value(_) => unreachable!("This should never be a value, as those get evaluated out into actual AST nodes in the evaluator")
+            },
+            // redefined since the call was compiled: evaluated as a call from there
+            _ => {
+                let mut form = args;
+                form.insert(0, function.clone());
+                let form = AstNode::List(form.into_iter().collect(), site.span.clone(), None);
+                evaluator.eval(form, env)
+            }
+        })?;
+        self.returned(value, depth);
+        Ok(())
+    }
+
+    // evaluates the expansion of a macro call in place of the call
+    fn expand(&mut self, site_index: usize, lambda: &LambdaEntry) -> Result<AstNode, EvalError> {
+        let closure = self.frame().closure.clone();
+        let site = &closure.proto.sites[site_index];
+        let env = self.variables(&closure, site);
+        self.lend_stack(|evaluator| {
+            let expansion = evaluator.expand_macro(lambda, unevaluated_args(site))?;
+            evaluator.eval(expansion, env)
+        })
+    }
+
+    // an environment with the variables around a call site, for the code evaluated by natives and macros
+    fn variables(&self, closure: &Closure, site: &CallSite) -> SharedEnvironment {
+        if site.variables.is_empty() {
+            return closure.env.clone();
+        }
+        let base = self.frame().base;
+        let mut env = Environment::new_child(closure.env.clone());
+        for (name, variable) in &site.variables {
+            let value = match variable {
+                Variable::Local(slot) => self.stack[base + *slot as usize].clone(),
+                Variable::Upvalue(index) => match &*closure.upvalues[*index as usize].borrow() {
+                    Upvalue::Open(position) => self.stack[*position].clone(),
+                    Upvalue::Closed(value) => value.clone(),
+                },
+            };
+            env.set_owned(EnvironmentEntry::new_ast_value(*name, value));
+        }
+        env.as_shared()
+    }
+
+    fn closure(&mut self, index: usize) -> AstNode {
+        let frame = self.frame();
+        let (enclosing, base) = (frame.closure.clone(), frame.base);
+        let proto = enclosing.proto.prototypes[index].clone();
+        let upvalues = proto
+            .upvalues
+            .iter()
+            .map(|(_, from)| match from {
+                Variable::Local(slot) => self.capture(base + *slot as usize),
+                Variable::Upvalue(index) => enclosing.upvalues[*index as usize].clone(),
+            })
+            .collect();
+        let lambda = LambdaEntry {
+            params: proto.params.clone(),
+            body: proto.body.clone(),
+            env: enclosing.env.clone(),
+            is_macro: false,
+            code: Some(CompiledBody::Bytecode(Rc::new(Closure {
+                proto,
+                upvalues,
+                env: enclosing.env.clone(),
+            }))),
+        };
+        AstNode::Lambda(Rc::new(lambda), None)
+    }
+
+    // the variables captured while their function runs are shared by all the closures
+    fn capture(&mut self, position: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in &self.open_upvalues {
+            if matches!(*upvalue.borrow(), Upvalue::Open(open) if open == position) {
+                return upvalue.clone();
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(position)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    // the slots from this position are going away, the upvalues keep their last value
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(position) if position >= from => {
+                    *upvalue = Upvalue::Closed(stack[position].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    // unwinds to the innermost try*, and runs its handler in place of the try*
+    fn catch(&mut self, err: EvalError) -> Result<(), EvalError> {
+        let handler = match self.handlers.pop() {
+            Some(handler) if err.is_catchable() => handler,
+            _ => return Err(err),
+        };
+        self.frames.truncate(handler.frames);
+        self.frames_dropped();
+        self.close_upvalues(handler.stack_len);
+        self.stack.truncate(handler.stack_len);
+        let (exception, stack_trace) = err.caught();
+        let frame = self.frame_mut();
+        frame.ip = handler.target;
+        frame.depth = handler.depth - 1;
+        let base = frame.base;
+        self.stack[base + handler.slot] = exception;
+        self.stack[base + handler.slot + 1] = stack_trace;
+        self.evaluator.truncate_call_stack(handler.depth - 1);
+        Ok(())
+    }
+}
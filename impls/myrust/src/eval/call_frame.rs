@@ -0,0 +1,119 @@
+use std::{fmt::Display, rc::Rc};
+
+use im_rc::{HashMap, Vector};
+
+use crate::read::{AstNode, HashMapKey, SourceSpan};
+
+// a native or fn* call in flight, used to build the lisp stack trace of errors
+#[derive(Clone, Debug)]
+pub struct CallFrame {
+    pub function: AstNode, // the evaluated FunctionPtr or Lambda being called
+    pub form: AstNode,     // the call form, as it was before evaluation
+    pub span: Option<Rc<SourceSpan>>,
+}
+
+impl CallFrame {
+    const MAX_FORM_LENGTH: usize = 80;
+
+    // whether the form is one the closures and bytecode backends compile inline instead of calling
+    // its native, like `(if c a b)`. no backend records a frame for them, malformed ones are calls.
+    pub fn is_inlined_form(form_elements: &Vector<AstNode>) -> bool {
+        match form_elements.front() {
+            Some(AstNode::UnresolvedSymbol(name, None)) => matches!(
+                (name.name(), form_elements.len()),
+                ("quote", 2) | ("if", 3 | 4) | ("def!" | "let*" | "fn*", 3) | ("do", 2..)
+            ),
+            _ => false,
+        }
+    }
+
+    // name of the native function, or the symbol the lambda was called through
+    pub fn name(&self) -> String {
+        match (&self.function, &self.form) {
+            (AstNode::FunctionPtr(entry, _), _) => entry.name().to_string(),
+            (AstNode::Lambda(_, _), AstNode::List(elements, _, _)) => match elements.front() {
+                Some(AstNode::UnresolvedSymbol(name, _)) => name.to_string(),
+                _ => "<anonymous fn*>".to_string(),
+            },
+            _ => "<anonymous fn*>".to_string(),
+        }
+    }
+
+    // the frame as a lisp value, for catch* handlers
+    pub fn to_ast_node(&self) -> AstNode {
+        let mut frame = HashMap::new();
+        frame.insert(HashMapKey::keyword("name"), AstNode::String(self.name()));
+        frame.insert(HashMapKey::keyword("form"), self.form.clone());
+        frame.insert(
+            HashMapKey::keyword("location"),
+            match &self.span {
+                Some(span) => AstNode::String(span.to_string()),
+                None => AstNode::Nil,
+            },
+        );
+        AstNode::HashMap(frame, None)
+    }
+}
+
+// the innermost frames of the call stack when an error was raised. capped, so that errors in deep
+// recursions stay cheap to raise and to catch, and shared by the errors wrapping the same one.
+#[derive(Clone, Debug)]
+pub struct StackTrace {
+    frames: Rc<[CallFrame]>,
+    omitted: usize, // the outer frames left out
+}
+
+impl StackTrace {
+    const MAX_FRAMES: usize = 50;
+
+    pub fn capture(call_stack: &[CallFrame]) -> Self {
+        let omitted = call_stack.len().saturating_sub(Self::MAX_FRAMES);
+        Self {
+            frames: call_stack[omitted..].into(),
+            omitted,
+        }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            frames: Rc::new([]),
+            omitted: 0,
+        }
+    }
+
+    // outermost first
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn omitted(&self) -> usize {
+        self.omitted
+    }
+
+    // the frames as a list of lisp values, for catch* handlers
+    pub fn to_ast_node(&self) -> AstNode {
+        AstNode::List(
+            self.frames
+                .iter()
+                .map(|frame| frame.to_ast_node())
+                .collect(),
+            None,
+            None,
+        )
+    }
+}
+
+impl Display for CallFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut form = self.form.to_string();
+        if form.chars().count() > Self::MAX_FORM_LENGTH {
+            form = form.chars().take(Self::MAX_FORM_LENGTH).collect::<String>() + "...";
+        }
+
+        write!(f, "{}: {}", self.name(), form)?;
+        if let Some(span) = &self.span {
+            write!(f, " ({})", span)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,534 @@
+use std::{cell::RefCell, rc::Rc};
+
+use im_rc::{HashMap, HashSet, Vector};
+
+use crate::{
+    functions::FunctionCallResultSuccess,
+    read::{AstNode, LambdaEntry, SourceSpan, Symbol},
+};
+
+use super::{
+    evaluator::bind_params, resolver::Scopes, CallFrame, CompiledBody, Environment,
+    EnvironmentEntry, EnvironmentEntryValue, EvalError, Evaluator, SharedEnvironment,
+};
+
+// the closures backend: each top level form is analyzed once into a tree of closures.
+// special forms are recognized statically, and macro calls are expanded when they are compiled,
+// or when they run if the macro didn't exist yet or was redefined. natives work the same as with
+// the tree walker: the forms they give back to evaluate are compiled on the fly, once per call site
+// as long as they give back the same form.
+
+// compiled code, run with its environment and the call stack depth of the eval loop running it
+pub type Code = Rc<dyn Fn(&Evaluator, &SharedEnvironment, usize) -> Result<Step, EvalError>>;
+
+pub enum Step {
+    Value(AstNode),
+    // code in tail position, run by the eval loop so that the stack doesn't grow.
+    // the position of the call is used for the errors of that code, like with the tree walker.
+    TailCall(Code, SharedEnvironment, Option<Rc<SourceSpan>>),
+}
+
+impl Evaluator {
+    // `span` is the position given to the forms without one, like the eval loop does
+    pub(super) fn compile(
+        &self,
+        ast: &AstNode,
+        env: &SharedEnvironment,
+        span: Option<Rc<SourceSpan>>,
+    ) -> Code {
+        Compiler {
+            evaluator: self.clone(),
+            root: env.get_root(),
+            scopes: Scopes::default(),
+            span,
+        }
+        .compile(ast)
+    }
+
+    pub(super) fn run_compiled(
+        &self,
+        code: &Code,
+        env: SharedEnvironment,
+    ) -> Result<AstNode, EvalError> {
+        let depth = self.enter_eval()?;
+        let stack_depth = self.call_stack_len();
+        let mut span = None;
+        let mut step = self.step().and_then(|()| code(self, &env, stack_depth));
+        let result = loop {
+            match step {
+                Ok(Step::Value(value)) => break Ok(value),
+                Ok(Step::TailCall(code, env, call_span)) => {
+                    if call_span.is_some() {
+                        span = call_span;
+                    }
+                    step = self.step().and_then(|()| code(self, &env, stack_depth));
+                }
+                Err(err) => break Err(self.traced(err, span.as_ref())),
+            }
+        };
+        self.truncate_call_stack(stack_depth);
+        self.restore_depth(depth);
+        result
+    }
+}
+
+// evaluates code that is not in tail position
+fn value_of(
+    code: &Code,
+    evaluator: &Evaluator,
+    env: &SharedEnvironment,
+) -> Result<AstNode, EvalError> {
+    evaluator.run_compiled(code, env.clone())
+}
+
+fn constant(value: AstNode) -> Code {
+    Rc::new(move |_, _, _| Ok(Step::Value(value.clone())))
+}
+
+struct Compiler {
+    evaluator: Evaluator,
+    // where macros are looked up
+    root: SharedEnvironment,
+    scopes: Scopes,
+    // the position of the innermost form read by the parser around the one compiled.
+    // forms built at runtime (e.g. by macros) get it, like with the tree walker.
+    span: Option<Rc<SourceSpan>>,
+}
+
+impl Compiler {
+    fn compile(&mut self, ast: &AstNode) -> Code {
+        match ast {
+            AstNode::UnresolvedSymbol(name, _) => {
+                let (name, address) = (*name, self.scopes.address_of(*name));
+                Rc::new(move |_, env, _| {
+                    let env = env.borrow();
+                    let entry = address
+                        .and_then(|address| env.find_at(address, name))
+                        .or_else(|| env.find(name))
+                        .ok_or_else(|| EvalError::SymbolNotFound(name.to_string()))?;
+                    Ok(Step::Value(entry.to_ast_node()))
+                })
+            }
+            AstNode::List(elements, span, _) if !elements.is_empty() => {
+                let outer = self.span.clone();
+                if span.is_some() {
+                    self.span = span.clone();
+                }
+                let code = self.compile_form(elements, &self.span.clone());
+                self.span = outer;
+                match span.clone() {
+                    // calls add the position themselves, special forms are wrapped
+                    Some(span) if !matches!(code, CompiledForm::Call(_)) => {
+                        let code = code.into_code();
+                        Rc::new(move |evaluator, env, stack_depth| {
+                            code(evaluator, env, stack_depth)
+                                .map_err(|err| evaluator.traced(err, Some(&span)))
+                        })
+                    }
+                    _ => code.into_code(),
+                }
+            }
+            AstNode::Vector(elements, meta) => {
+                let elements = self.compile_all(elements.iter());
+                let meta = meta.clone();
+                Rc::new(move |evaluator, env, _| {
+                    let elements = Result::from_iter(
+                        elements.iter().map(|code| value_of(code, evaluator, env)),
+                    )?;
+                    Ok(Step::Value(AstNode::Vector(elements, meta.clone())))
+                })
+            }
+            AstNode::HashMap(hm, meta) => {
+                let entries: Vec<(Code, Code)> = hm
+                    .iter()
+                    .map(|(key, value)| (self.compile(&key.to_ast_node()), self.compile(value)))
+                    .collect();
+                let meta = meta.clone();
+                Rc::new(move |evaluator, env, _| {
+                    let mut hm = HashMap::new();
+                    for (key, value) in &entries {
+                        hm.insert(
+                            value_of(key, evaluator, env)?.try_unwrap_hashmap_key()?,
+                            value_of(value, evaluator, env)?,
+                        );
+                    }
+                    Ok(Step::Value(AstNode::HashMap(hm, meta.clone())))
+                })
+            }
+            AstNode::Set(set, meta) => {
+                let elements: Vec<AstNode> = set.iter().map(|x| x.to_ast_node()).collect();
+                let elements = self.compile_all(elements.iter());
+                let meta = meta.clone();
+                Rc::new(move |evaluator, env, _| {
+                    let mut set = HashSet::new();
+                    for element in &elements {
+                        set.insert(value_of(element, evaluator, env)?.try_unwrap_hashmap_key()?);
+                    }
+                    Ok(Step::Value(AstNode::Set(set, meta.clone())))
+                })
+            }
+            // everything else, including the empty list, evaluates to itself
+            any => constant(any.clone()),
+        }
+    }
+
+    fn compile_all<'a>(&mut self, elements: impl Iterator<Item = &'a AstNode>) -> Vec<Code> {
+        elements.map(|element| self.compile(element)).collect()
+    }
+
+    // special forms are only recognized if their name isn't a local variable.
+    // malformed ones are compiled as calls to the natives, to get the same errors.
+    fn compile_form(
+        &mut self,
+        elements: &Vector<AstNode>,
+        span: &Option<Rc<SourceSpan>>,
+    ) -> CompiledForm {
+        let head = match elements.front() {
+            Some(AstNode::UnresolvedSymbol(name, _)) if self.scopes.address_of(*name).is_none() => {
+                Some(*name)
+            }
+            _ => None,
+        };
+        match (head.map(|name| name.name()), elements.len()) {
+            (Some("quote"), 2) => CompiledForm::Special(constant(elements[1].clone())),
+            (Some("if"), 3 | 4) => CompiledForm::Special(self.compile_if(elements)),
+            (Some("do"), len) if len > 1 => CompiledForm::Special(self.compile_do(elements)),
+            (Some("def!"), 3) => match &elements[1] {
+                AstNode::UnresolvedSymbol(name, _) => {
+                    CompiledForm::Special(self.compile_def(*name, &elements[2]))
+                }
+                _ => self.compile_call(elements, span, None),
+            },
+            (Some("let*"), 3) => match self.compile_let(elements) {
+                Some(code) => CompiledForm::Special(code),
+                None => self.compile_call(elements, span, None),
+            },
+            (Some("fn*"), 3) => match self.compile_fn(elements) {
+                Some(code) => CompiledForm::Special(code),
+                None => self.compile_call(elements, span, None),
+            },
+            // the call still checks that the macro wasn't redefined when it runs
+            _ => match head.and_then(|name| self.find_macro(name)) {
+                Some(lambda) => {
+                    let expansion = match self.compile_expansion(elements, &lambda) {
+                        Ok(code) => Some((lambda, code)),
+                        // the call fails the same way when it runs
+                        Err(_) => None,
+                    };
+                    self.compile_call(elements, span, expansion)
+                }
+                None => self.compile_call(elements, span, None),
+            },
+        }
+    }
+
+    // compiling the expansion of a macro call counts as a nested evaluation, like running it would,
+    // so that macros expanding forever fail instead of overflowing the stack
+    fn compile_expansion(
+        &mut self,
+        elements: &Vector<AstNode>,
+        lambda: &LambdaEntry,
+    ) -> Result<Code, EvalError> {
+        let depth = self.evaluator.enter_eval()?;
+        let args = elements.iter().skip(1).cloned().collect();
+        let code = self
+            .evaluator
+            .expand_macro(lambda, args)
+            .map(|expansion| self.compile(&expansion));
+        self.evaluator.restore_depth(depth);
+        code
+    }
+
+    fn find_macro(&self, name: Symbol) -> Option<Rc<LambdaEntry>> {
+        let entry = self.root.borrow().find(name)?;
+        match entry.value() {
+            EnvironmentEntryValue::Value(AstNode::Lambda(lambda, _)) if lambda.is_macro => {
+                Some(lambda.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn compile_if(&mut self, elements: &Vector<AstNode>) -> Code {
+        let condition = self.compile(&elements[1]);
+        let then = self.compile(&elements[2]);
+        let otherwise = elements.get(3).map(|ast| self.compile(ast));
+        Rc::new(
+            move |evaluator, env, stack_depth| match value_of(&condition, evaluator, env)? {
+                AstNode::Bool(false) | AstNode::Nil => match &otherwise {
+                    Some(otherwise) => otherwise(evaluator, env, stack_depth),
+                    None => Ok(Step::Value(AstNode::Nil)),
+                },
+                _ => then(evaluator, env, stack_depth),
+            },
+        )
+    }
+
+    fn compile_do(&mut self, elements: &Vector<AstNode>) -> Code {
+        let mut body = self.compile_all(elements.iter().skip(1));
+        let last = body.pop().unwrap();
+        Rc::new(move |evaluator, env, stack_depth| {
+            for code in &body {
+                value_of(code, evaluator, env)?;
+            }
+            last(evaluator, env, stack_depth)
+        })
+    }
+
+    fn compile_def(&mut self, name: Symbol, value: &AstNode) -> Code {
+        let value = self.compile(value);
+        Rc::new(move |evaluator, env, _| {
+            let value = value_of(&value, evaluator, env)?;
+            env.get_root()
+                .borrow_mut()
+                .set_owned(EnvironmentEntry::new_ast_value(name, value.clone()));
+            Ok(Step::Value(value))
+        })
+    }
+
+    fn compile_let(&mut self, elements: &Vector<AstNode>) -> Option<Code> {
+        let bindings = match &elements[1] {
+            AstNode::List(bindings, _, _) | AstNode::Vector(bindings, _) => bindings,
+            _ => return None,
+        };
+        if bindings.len() % 2 != 0 {
+            return None;
+        }
+        let mut names = vec![];
+        for name in bindings.iter().step_by(2) {
+            match name {
+                AstNode::UnresolvedSymbol(name, _) => names.push(*name),
+                _ => return None,
+            }
+        }
+
+        // each value sees the variables bound before it
+        self.scopes.push();
+        self.scopes.declare_pending(names.iter().copied());
+        let mut values = vec![];
+        for (name, value) in names.iter().zip(bindings.iter().skip(1).step_by(2)) {
+            values.push((*name, self.compile(value)));
+            self.scopes.declare(*name);
+        }
+        let body = self.compile(&elements[2]);
+        self.scopes.pop();
+
+        Some(Rc::new(move |evaluator, env, stack_depth| {
+            let env = Environment::new_child(env.clone()).as_shared();
+            for (name, value) in &values {
+                let value = value_of(value, evaluator, &env)?;
+                env.borrow_mut()
+                    .set_owned(EnvironmentEntry::new_ast_value(*name, value));
+            }
+            body(evaluator, &env, stack_depth)
+        }))
+    }
+
+    fn compile_fn(&mut self, elements: &Vector<AstNode>) -> Option<Code> {
+        let params = match &elements[1] {
+            AstNode::List(params, _, _) | AstNode::Vector(params, _) => params,
+            _ => return None,
+        };
+        let mut names = vec![];
+        for param in params {
+            match param {
+                AstNode::UnresolvedSymbol(name, _) => names.push(*name),
+                _ => return None,
+            }
+        }
+
+        self.scopes.push();
+        self.scopes.declare_params(params);
+        let code = self.compile(&elements[2]);
+        self.scopes.pop();
+
+        let body = Rc::new(elements[2].clone());
+        Some(Rc::new(move |_, env, _| {
+            let lambda = LambdaEntry {
+                params: names.clone(),
+                body: body.clone(),
+                env: env.clone(),
+                is_macro: false,
+                code: Some(CompiledBody::Closures(code.clone())),
+            };
+            Ok(Step::Value(AstNode::Lambda(Rc::new(lambda), None)))
+        }))
+    }
+
+    fn compile_call(
+        &mut self,
+        elements: &Vector<AstNode>,
+        span: &Option<Rc<SourceSpan>>,
+        expansion: Option<(Rc<LambdaEntry>, Code)>,
+    ) -> CompiledForm {
+        let call = Call {
+            function: self.compile(&elements[0]),
+            params: self.compile_all(elements.iter().skip(1)),
+            form: AstNode::List(elements.clone(), span.clone(), None),
+            span: span.clone(),
+            scopes: self.scopes.clone(),
+            expansion: RefCell::new(expansion),
+            tail_form: RefCell::new(None),
+        };
+        CompiledForm::Call(Rc::new(move |evaluator, env, stack_depth| {
+            call.run(evaluator, env, stack_depth)
+                .map_err(|err| evaluator.traced(err, call.span.as_ref()))
+        }))
+    }
+}
+
+enum CompiledForm {
+    Special(Code),
+    Call(Code),
+}
+
+impl CompiledForm {
+    fn into_code(self) -> Code {
+        match self {
+            CompiledForm::Special(code) | CompiledForm::Call(code) => code,
+        }
+    }
+}
+
+// a call to a function, a native or a macro only known when the code runs
+struct Call {
+    function: Code,
+    params: Vec<Code>,
+    form: AstNode,
+    span: Option<Rc<SourceSpan>>,
+    // the variables around the call, to compile what a macro expands to
+    scopes: Scopes,
+    // the last expansion, reused as long as the macro isn't redefined
+    expansion: RefCell<Option<(Rc<LambdaEntry>, Code)>>,
+    // the last form given back by a native (like the handler of try*) or the body of a lambda
+    // made at runtime, reused as long as it's the same list. the forms of eval are new each time.
+    tail_form: RefCell<Option<(Vector<AstNode>, Code)>>,
+}
+
+impl Call {
+    fn run(
+        &self,
+        evaluator: &Evaluator,
+        env: &SharedEnvironment,
+        stack_depth: usize,
+    ) -> Result<Step, EvalError> {
+        let func = value_of(&self.function, evaluator, env)?;
+
+        evaluator.push_call_frame(
+            CallFrame {
+                function: func.clone(),
+                form: self.form.clone(),
+                span: self.span.clone(),
+            },
+            stack_depth,
+        );
+
+        match func {
+            AstNode::FunctionPtr(definition, _) => match definition.value() {
+                EnvironmentEntryValue::NativeFunction(func) => {
+                    let params = if func.evaluates_arguments() {
+                        self.values(evaluator, env)?
+                    } else {
+                        self.unevaluated_params()
+                    };
+                    match func.run_with_params(params, env.clone(), evaluator.clone())? {
+                        FunctionCallResultSuccess::Value(value) => Ok(Step::Value(value)),
+                        FunctionCallResultSuccess::TailCall(tailcalldata) => {
+                            let (ast, env) = tailcalldata.destructure();
+                            Ok(Step::TailCall(
+                                self.compile_tail_form(evaluator, &ast, &env),
+                                env,
+                                self.span.clone(),
+                            ))
+                        }
+                    }
+                }
+                EnvironmentEntryValue::Value(_) => unreachable!("This should never be a value, as those get evaluated out into actual AST nodes in the evaluator")
+            },
+            AstNode::Lambda(lambda, _) if lambda.is_macro => {
+                Ok(Step::TailCall(
+                    self.expand(evaluator, env, &lambda)?,
+                    env.clone(),
+                    self.span.clone(),
+                ))
+            }
+            AstNode::Lambda(lambda, _) => {
+                let new_env = bind_params(&lambda, self.values(evaluator, env)?)?;
+                evaluator.trace_lambda_funcall(&lambda.body, &lambda.params, &new_env);
+                let code = match &lambda.code {
+                    Some(CompiledBody::Closures(code)) => code.clone(),
+                    // created by the fn* native, from code built at runtime
+                    _ => self.compile_tail_form(evaluator, &lambda.body, env),
+                };
+                Ok(Step::TailCall(
+                    code,
+                    new_env.as_shared(),
+                    self.span.clone(),
+                ))
+            }
+            node => Err(EvalError::InvalidFunctionCallNodeType(node)),
+        }
+    }
+
+    fn values(
+        &self,
+        evaluator: &Evaluator,
+        env: &SharedEnvironment,
+    ) -> Result<Vec<AstNode>, EvalError> {
+        Result::from_iter(
+            self.params
+                .iter()
+                .map(|code| value_of(code, evaluator, env)),
+        )
+    }
+
+    fn unevaluated_params(&self) -> Vec<AstNode> {
+        match &self.form {
+            AstNode::List(elements, _, _) => elements.iter().skip(1).cloned().collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn compile_tail_form(
+        &self,
+        evaluator: &Evaluator,
+        ast: &AstNode,
+        env: &SharedEnvironment,
+    ) -> Code {
+        let AstNode::List(elements, _, _) = ast else {
+            return evaluator.compile(ast, env, self.span.clone());
+        };
+        // the cached list is kept alive, so another one can't take its place in memory
+        if let Some((form, code)) = &*self.tail_form.borrow() {
+            if form.ptr_eq(elements) {
+                return code.clone();
+            }
+        }
+        let code = evaluator.compile(ast, env, self.span.clone());
+        *self.tail_form.borrow_mut() = Some((elements.clone(), code.clone()));
+        code
+    }
+
+    fn expand(
+        &self,
+        evaluator: &Evaluator,
+        env: &SharedEnvironment,
+        lambda: &Rc<LambdaEntry>,
+    ) -> Result<Code, EvalError> {
+        if let Some((expanded, code)) = &*self.expansion.borrow() {
+            if Rc::ptr_eq(expanded, lambda) {
+                return Ok(code.clone());
+            }
+        }
+        let expansion = evaluator.expand_macro(lambda, self.unevaluated_params())?;
+        let code = Compiler {
+            evaluator: evaluator.clone(),
+            root: env.get_root(),
+            scopes: self.scopes.clone(),
+            span: self.span.clone(),
+        }
+        .compile(&expansion);
+        *self.expansion.borrow_mut() = Some((lambda.clone(), code.clone()));
+        Ok(code)
+    }
+}
@@ -1,6 +1,10 @@
-use std::fmt::Display;
+use std::{fmt::Display, rc::Rc};
 
-use crate::read::AstNode;
+use im_rc::vector;
+
+use crate::read::{AstNode, SourceSpan};
+
+use super::{CallFrame, StackTrace};
 
 #[derive(Debug)]
 pub enum EvalError {
@@ -16,6 +20,23 @@ pub enum EvalError {
         got: AstNode,
     },
     CustomException(AstNode),
+    ArithmeticError(String), // division by zero, overflow, invalid modulus...
+    StackOverflow(usize),    // too many nested evaluations, the maximum depth
+    // the evaluation is stopped, try* doesn't catch it
+    Aborted(AbortReason),
+    // error raised while evaluating the form at `span`, with the lisp call stack at that moment
+    Traced {
+        error: Box<EvalError>,
+        span: Option<Rc<SourceSpan>>,
+        stack: StackTrace,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AbortReason {
+    StepBudget,  // see Evaluator::set_step_budget
+    Deadline,    // see Evaluator::set_deadline
+    Interrupted, // see Evaluator::interrupt_handle
 }
 
 impl EvalError {
@@ -25,6 +46,105 @@ impl EvalError {
     {
         EvalError::CustomException(AstNode::String(s.to_string()))
     }
+
+    // attaches the position of the failing form and the call stack, unless they are already there.
+    // the innermost form with a known position wins.
+    pub fn traced(self, span: Option<&Rc<SourceSpan>>, stack: &[CallFrame]) -> EvalError {
+        match self {
+            EvalError::Traced {
+                error,
+                span: None,
+                stack,
+            } => EvalError::Traced {
+                error,
+                span: span.cloned(),
+                stack,
+            },
+            err @ EvalError::Traced { .. } => err,
+            err => EvalError::Traced {
+                error: Box::new(err),
+                span: span.cloned(),
+                stack: StackTrace::capture(stack),
+            },
+        }
+    }
+
+    // the error itself, without the position and stack information
+    pub fn into_inner(self) -> EvalError {
+        match self {
+            EvalError::Traced { error, .. } => error.into_inner(),
+            err => err,
+        }
+    }
+
+    pub fn is_catchable(&self) -> bool {
+        match self {
+            EvalError::Traced { error, .. } => error.is_catchable(),
+            EvalError::Aborted(_) => false,
+            _ => true,
+        }
+    }
+
+    // lisp calls in flight when the error was raised
+    pub fn stack_trace(&self) -> StackTrace {
+        match self {
+            EvalError::Traced { stack, .. } => stack.clone(),
+            _ => StackTrace::empty(),
+        }
+    }
+
+    // what a catch* handler gets: the exception value, and the stack trace as a list of frames
+    pub fn caught(self) -> (AstNode, AstNode) {
+        let stack_trace = self.stack_trace().to_ast_node();
+        let exception_data = match self.into_inner() {
+            EvalError::SymbolNotFound(symbol) => {
+                AstNode::String(format!("\'{}\' not found", symbol))
+            }
+            EvalError::InvalidFunctionCallNodeType(node) => AstNode::List(
+                vector![AstNode::create_keyword("InvalidFunctionCallNodeType"), node],
+                None,
+                None,
+            ),
+            EvalError::ParameterCountError {
+                expected_min: _,
+                expected_max: _,
+                provided: _,
+            } => AstNode::List(
+                vector![
+                    AstNode::create_keyword("ParameterCount"), /* it would be great to provide more parameters, but whatever */
+                ],
+                None,
+                None,
+            ),
+            EvalError::TypeError { expected, got } => AstNode::List(
+                vector![
+                    AstNode::create_keyword("TypeError"),
+                    AstNode::String(expected),
+                    got,
+                ],
+                None,
+                None,
+            ),
+            EvalError::CustomException(custom) => custom,
+            EvalError::ArithmeticError(msg) => AstNode::Vector(
+                vector![
+                    AstNode::create_keyword("ArithmeticError"),
+                    AstNode::String(msg),
+                ],
+                None,
+            ),
+            EvalError::StackOverflow(max_depth) => AstNode::Vector(
+                vector![
+                    AstNode::create_keyword("StackOverflow"),
+                    AstNode::Int(max_depth as i64),
+                ],
+                None,
+            ),
+            EvalError::Aborted(_) => unreachable!("aborts can't be caught"),
+            EvalError::Traced { .. } => unreachable!("into_inner strips the trace"),
+        };
+        (exception_data, stack_trace)
+    }
 }
 
 impl Display for EvalError {
@@ -47,6 +167,25 @@ impl Display for EvalError {
                 write!(f, "Expected \'{}\' - found \'{}\'", expected, got)
             }
             EvalError::CustomException(node) => write!(f, "Custom exception: {:?}", node),
+            EvalError::ArithmeticError(msg) => write!(f, "Arithmetic error: {}", msg),
+            EvalError::StackOverflow(max_depth) => write!(
+                f,
+                "Stack overflow: more than {} nested evaluations",
+                max_depth
+            ),
+            EvalError::Aborted(AbortReason::StepBudget) => {
+                write!(f, "Evaluation aborted: out of steps")
+            }
+            EvalError::Aborted(AbortReason::Deadline) => {
+                write!(f, "Evaluation aborted: deadline passed")
+            }
+            EvalError::Aborted(AbortReason::Interrupted) => write!(f, "Evaluation interrupted"),
+            EvalError::Traced {
+                error,
+                span: Some(span),
+                ..
+            } => write!(f, "{}: {}", span, error),
+            EvalError::Traced { error, .. } => write!(f, "{}", error),
         }
     }
 }
@@ -1,9 +1,25 @@
-use crate::functions::{FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
-use std::{cell::RefCell, collections::BTreeSet, rc::Rc};
+use crate::functions::{
+    FunctionCallResult, FunctionCallResultSuccess, NativeFunction, TailCallData,
+};
+use im_rc::Vector;
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    ops::Deref,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
-use crate::read::AstNode;
+use crate::read::{AstNode, HashMapKey, LambdaEntry, SourceSpan, Symbol};
 
-use super::{Environment, EnvironmentEntry, EnvironmentEntryValue, EvalError, SharedEnvironment};
+use super::{
+    AbortReason, CallFrame, Closure, Code, Environment, EnvironmentEntry, EnvironmentEntryValue,
+    EvalError, SharedEnvironment,
+};
 
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Clone, Copy)]
 #[repr(u8)]
@@ -12,13 +28,97 @@ pub enum TraceFlag {
     TraceFnCalls = 2,
 }
 
+// how forms are evaluated, chosen at startup
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Backend {
+    // walks the AST of the forms
+    TreeWalker,
+    // compiles each top level form to closures first, see compiler.rs
+    Closures,
+    // compiles each top level form to bytecode for a stack machine, see bytecode/
+    Bytecode,
+}
+
+// the body of a lambda, compiled by the backend that created it
+#[derive(Clone)]
+pub enum CompiledBody {
+    Closures(Code),
+    Bytecode(Rc<Closure>),
+}
+
+// the default maximum depth of nested evaluations, see main.rs for the stack it needs
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+// reading the clock is slow compared to a step, the deadline is checked every so many steps
+const STEPS_BETWEEN_CLOCK_CHECKS: u32 = 1024;
+
 pub struct EvaluatorData {
     trace_config: BTreeSet<TraceFlag>,
+    call_stack: Vec<CallFrame>,
+    backend: Backend,
+    // the stack of the bytecode vm, while no vm is using it
+    vm_stack: Vec<AstNode>,
+    // nested evaluations in progress, and how many are allowed before a StackOverflow error
+    depth: usize,
+    max_depth: usize,
+    // limits for untrusted code: the steps of evaluation left, and when to stop
+    steps_left: Option<u64>,
+    deadline: Option<Instant>,
+    steps_until_clock_check: u32,
+    // set from outside to stop the evaluation, like by ctrl-c in the repl
+    interrupt: Arc<AtomicBool>,
 }
 impl EvaluatorData {
     fn new() -> EvaluatorData {
         EvaluatorData {
             trace_config: BTreeSet::new(),
+            call_stack: vec![],
+            backend: Backend::TreeWalker,
+            vm_stack: vec![],
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            steps_left: None,
+            deadline: None,
+            steps_until_clock_check: 0,
+            interrupt: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn spend_step(&mut self) -> Result<(), EvalError> {
+        if let Some(steps_left) = &mut self.steps_left {
+            if *steps_left == 0 {
+                return Err(EvalError::Aborted(AbortReason::StepBudget));
+            }
+            *steps_left -= 1;
+        }
+        if let Some(deadline) = self.deadline {
+            if self.steps_until_clock_check > 0 {
+                self.steps_until_clock_check -= 1;
+            } else if Instant::now() >= deadline {
+                return Err(EvalError::Aborted(AbortReason::Deadline));
+            } else {
+                self.steps_until_clock_check = STEPS_BETWEEN_CLOCK_CHECKS;
+            }
+        }
+        Ok(())
+    }
+}
+
+// a form to evaluate. arguments are borrowed from the calling form and lambda bodies are shared,
+// so that calling a function doesn't copy its code.
+pub enum Form<'a> {
+    Borrowed(&'a AstNode),
+    Owned(AstNode),
+    Shared(Rc<AstNode>),
+}
+
+impl Deref for Form<'_> {
+    type Target = AstNode;
+
+    fn deref(&self) -> &AstNode {
+        match self {
+            Form::Borrowed(ast) => ast,
+            Form::Owned(ast) => ast,
+            Form::Shared(ast) => ast,
         }
     }
 }
@@ -29,45 +129,100 @@ pub struct Evaluator {
 }
 
 impl Evaluator {
-    fn eval_ast_value(&self, ast: AstNode, env: &SharedEnvironment) -> FunctionCallResult {
+    fn eval_ast_value(&self, ast: &AstNode, env: &SharedEnvironment) -> FunctionCallResult {
         Ok(FunctionCallResultSuccess::Value(match ast {
-            AstNode::List(_) => unreachable!(),
-            AstNode::Int(num) => AstNode::Int(num),
-            AstNode::String(str) => AstNode::String(str),
-            AstNode::Bool(b) => AstNode::Bool(b),
-            AstNode::Nil => AstNode::Nil,
-            AstNode::UnresolvedSymbol(name) => env
-                .borrow()
-                .find(&name)
-                .ok_or(EvalError::SymbolNotFound(name))?
-                .to_ast_node(),
-            AstNode::FunctionPtr(fptr) => AstNode::FunctionPtr(fptr),
-            AstNode::Lambda(l) => AstNode::Lambda(l),
-            AstNode::Vector(content) => AstNode::Vector(Result::from_iter(
-                content.into_iter().map(|a| self.eval(a, env.clone())),
-            )?),
-            AstNode::Atom(rc) => AstNode::Atom(rc),
-            AstNode::HashMap(content) => {
-                let iter_kv = Result::from_iter(content.into_iter().map(
-                    |a| -> Result<(String, AstNode), EvalError> {
-                        Ok((a.0, self.eval(a.1, env.clone())?))
+            AstNode::UnresolvedSymbol(name, address) => {
+                let env = env.borrow();
+                address
+                    .and_then(|address| env.find_at(address, *name))
+                    .or_else(|| env.find(*name))
+                    .ok_or_else(|| EvalError::SymbolNotFound(name.to_string()))?
+                    .to_ast_node()
+            }
+            AstNode::Vector(content, meta) => AstNode::Vector(
+                Result::from_iter(content.iter().map(|a| self.eval_borrowed(a, env.clone())))?,
+                meta.clone(),
+            ),
+            AstNode::HashMap(content, meta) => {
+                let iter_kv = Result::from_iter(content.iter().map(
+                    |a| -> Result<(HashMapKey, AstNode), EvalError> {
+                        let key = self.eval(a.0.to_ast_node(), env.clone())?;
+                        Ok((
+                            key.try_unwrap_hashmap_key()?,
+                            self.eval_borrowed(a.1, env.clone())?,
+                        ))
                     },
                 ))?;
 
-                AstNode::HashMap(iter_kv)
+                AstNode::HashMap(iter_kv, meta.clone())
             }
+            AstNode::Set(content, meta) => {
+                let elements = Result::from_iter(content.iter().map(
+                    |element| -> Result<HashMapKey, EvalError> {
+                        self.eval(element.to_ast_node(), env.clone())?
+                            .try_unwrap_hashmap_key()
+                    },
+                ))?;
+
+                AstNode::Set(elements, meta.clone())
+            }
+            // sorted collections are only built at runtime by natives, so the content is already
+            // evaluated. empty lists evaluate to themselves, like all the other values.
+            any => any.clone(),
         }))
     }
 
-    pub fn eval(&self, mut ast: AstNode, mut env: SharedEnvironment) -> Result<AstNode, EvalError> {
+    pub fn eval(&self, ast: AstNode, env: SharedEnvironment) -> Result<AstNode, EvalError> {
+        match self.backend() {
+            Backend::TreeWalker => self.eval_form(Form::Owned(ast), env),
+            Backend::Closures => self.run_compiled(&self.compile(&ast, &env, None), env),
+            Backend::Bytecode => self.eval_bytecode(&ast, env),
+        }
+    }
+
+    pub fn eval_borrowed(
+        &self,
+        ast: &AstNode,
+        env: SharedEnvironment,
+    ) -> Result<AstNode, EvalError> {
+        match self.backend() {
+            Backend::TreeWalker => self.eval_form(Form::Borrowed(ast), env),
+            Backend::Closures => self.run_compiled(&self.compile(ast, &env, None), env),
+            Backend::Bytecode => self.eval_bytecode(ast, env),
+        }
+    }
+
+    fn eval_form(&self, ast: Form, env: SharedEnvironment) -> Result<AstNode, EvalError> {
+        let depth = self.enter_eval()?;
+        let stack_depth = self.data.borrow().call_stack.len();
+        let result = self.eval_tco_loop(ast, env, stack_depth);
+        self.data.borrow_mut().call_stack.truncate(stack_depth);
+        self.restore_depth(depth);
+        result
+    }
+
+    fn eval_tco_loop(
+        &self,
+        mut ast: Form,
+        mut env: SharedEnvironment,
+        stack_depth: usize,
+    ) -> Result<AstNode, EvalError> {
+        // position of the innermost form read by the parser, used for error reporting.
+        // forms built at runtime (e.g. by macros) don't have one, so the last known one is kept.
+        let mut span = None;
         loop {
-            let tailcall_result = match ast {
-                AstNode::List(empty) if empty.len() == 0 => {
-                    Ok(FunctionCallResultSuccess::Value(AstNode::List(empty)))
-                }
-                AstNode::List(mut list) => self.eval_funcall(list.remove(0), list, env),
-                any => self.eval_ast_value(any, &env),
-            }?;
+            let tailcall_result = self
+                .step()
+                .and_then(|()| match &*ast {
+                    AstNode::List(list, list_span, _) if !list.is_empty() => {
+                        if list_span.is_some() {
+                            span = list_span.clone();
+                        }
+                        self.eval_funcall(list, span.clone(), env, stack_depth)
+                    }
+                    any => self.eval_ast_value(any, &env),
+                })
+                .map_err(|err| err.traced(span.as_ref(), &self.data.borrow().call_stack))?;
 
             match tailcall_result {
                 FunctionCallResultSuccess::Value(v) => return Ok(v),
@@ -81,93 +236,196 @@ impl Evaluator {
     }
     fn eval_funcall(
         &self,
-        func: AstNode,
-        params: Vec<AstNode>,
+        form_elements: &Vector<AstNode>,
+        span: Option<Rc<SourceSpan>>,
         env: SharedEnvironment,
+        stack_depth: usize,
     ) -> FunctionCallResult {
-        let func = self.eval(func, env.clone())?;
+        let form = AstNode::List(form_elements.clone(), span.clone(), None);
+        let mut params = form_elements.iter();
+        let func = self.eval_borrowed(params.next().unwrap(), env.clone())?;
+
+        if !CallFrame::is_inlined_form(form_elements) {
+            self.push_call_frame(
+                CallFrame {
+                    function: func.clone(),
+                    form,
+                    span,
+                },
+                stack_depth,
+            );
+        }
 
         match func {
-            AstNode::FunctionPtr(definition) => {
+            AstNode::FunctionPtr(definition, _) => {
                 // run
                 match definition.value() {
                     EnvironmentEntryValue::NativeFunction(func) => func.eval_params_and_run(params, env, self.clone()),
                     EnvironmentEntryValue::Value(_) => unreachable!("This should never be a value, as those get evaluated out into actual AST nodes in the evaluator")
                 }
             }
-            AstNode::Lambda(definition) => {
+            AstNode::Lambda(definition, _) => {
                 let lambda = &*definition;
 
-                let mut new_env = Environment::new_child(lambda.env.clone());
-
-                let mut params_names = lambda.params.clone();
                 let mut params_values = vec![];
-
                 for p in params {
                     if lambda.is_macro {
                         // macros don't evaluate their arguments - they just get passed in
-                        params_values.push(p);
+                        params_values.push(p.clone());
                     } else {
-                        params_values.push(self.eval(p, env.clone())?);
+                        params_values.push(self.eval_borrowed(p, env.clone())?);
                     }
                 }
 
-                while params_names.len() > 0 {
-                    let name = params_names.remove(0);
-                    if name == "&" {
-                        // special case: now we bind all the remaining values to the last parameter
-                        let name = params_names.remove(0);
-                        if params_names.len() != 0 {
-                            // sanity check: no more parameters after this
-                            return Err(EvalError::custom_exception_str(
-                                "& does not appear in penultimate position in the function parameters"
-                                    .to_string(),
-                            ));
-                        }
-                        let value = AstNode::List(params_values);
-                        params_values = vec![];
-                        new_env.set_owned(EnvironmentEntry::new_ast_value(name, value));
-                        break;
-                    }
-
-                    if params_values.len() == 0 {
-                        return Err(EvalError::custom_exception_str(
-                            "Not enough parameters supplied to lambda function call".to_string(),
-                        ));
-                    }
-                    let value = params_values.remove(0);
-                    new_env.set_owned(EnvironmentEntry::new_ast_value(name, value));
-                }
-                if params_values.len() != 0 {
-                    return Err(EvalError::custom_exception_str(
-                        "Too many parameters passed to lambda call",
-                    ));
+                if lambda.is_macro {
+                    let expansion = self.expand_macro(lambda, params_values)?;
+                    return Ok(FunctionCallResultSuccess::new_tailcall(expansion, env));
                 }
 
+                let new_env = bind_params(lambda, params_values)?;
                 self.trace_lambda_funcall(&lambda.body, &lambda.params, &new_env);
 
-                // TODO: Cloning the AST for evaluating it is terribly inefficient! Use references instead.
-                if !lambda.is_macro {
-                    // normal function application means we can actually tailcall it
-                    Ok(FunctionCallResultSuccess::new_tailcall(
-                        lambda.body.clone(),
-                        new_env.as_shared(),
-                    ))
-                } else {
-                    let result = self.eval(lambda.body.clone(), new_env.as_shared())?;
-                    Ok(FunctionCallResultSuccess::new_tailcall(result, env))
-                }
+                // normal function application means we can actually tailcall it
+                Ok(FunctionCallResultSuccess::TailCall(TailCallData::shared(
+                    lambda.body.clone(),
+                    new_env.as_shared(),
+                )))
             }
             node => Err(EvalError::InvalidFunctionCallNodeType(node)),
         }
     }
 
+    // the code returned by a macro called with the given (unevaluated) arguments
+    pub(super) fn expand_macro(
+        &self,
+        lambda: &LambdaEntry,
+        params_values: Vec<AstNode>,
+    ) -> Result<AstNode, EvalError> {
+        // the virtual machine binds the parameters itself
+        if let Some(CompiledBody::Bytecode(closure)) = &lambda.code {
+            return self.run_closure(closure.clone(), params_values);
+        }
+        let new_env = bind_params(lambda, params_values)?;
+        self.trace_lambda_funcall(&lambda.body, &lambda.params, &new_env);
+        match &lambda.code {
+            Some(CompiledBody::Closures(code)) => self.run_compiled(code, new_env.as_shared()),
+            _ => self.eval_borrowed(&lambda.body, new_env.as_shared()),
+        }
+    }
+    // tail calls replace the frames pushed by the same eval loop, so that the stack stays bounded.
+    // the last fn* frame is kept below a native one, otherwise the body of a lambda calling a
+    // native in tail position (like `if`) would hide the lambda from the stack trace.
+    pub(super) fn push_call_frame(&self, frame: CallFrame, stack_depth: usize) {
+        let call_stack = &mut self.data.borrow_mut().call_stack;
+        let keep_lambda_frame = !matches!(frame.function, AstNode::Lambda(_, _))
+            && matches!(
+                call_stack.get(stack_depth),
+                Some(CallFrame {
+                    function: AstNode::Lambda(_, _),
+                    ..
+                })
+            );
+        call_stack.truncate(stack_depth + keep_lambda_frame as usize);
+        call_stack.push(frame);
+    }
+
     pub fn new() -> Self {
         Self {
             data: Rc::new(RefCell::new(EvaluatorData::new())),
         }
     }
 
+    pub fn backend(&self) -> Backend {
+        self.data.borrow().backend
+    }
+
+    pub fn set_backend(&self, backend: Backend) {
+        self.data.borrow_mut().backend = backend;
+    }
+
+    pub(super) fn take_vm_stack(&self) -> Vec<AstNode> {
+        std::mem::take(&mut self.data.borrow_mut().vm_stack)
+    }
+
+    pub(super) fn put_vm_stack(&self, stack: Vec<AstNode>) {
+        self.data.borrow_mut().vm_stack = stack;
+    }
+
+    pub fn set_max_depth(&self, max_depth: usize) {
+        self.data.borrow_mut().max_depth = max_depth;
+    }
+
+    // counts one more nested evaluation, giving back the depth to restore when it's done.
+    // past the maximum depth this fails, instead of overflowing the stack of the process.
+    pub(super) fn enter_eval(&self) -> Result<usize, EvalError> {
+        let mut data = self.data.borrow_mut();
+        if data.depth >= data.max_depth {
+            return Err(EvalError::StackOverflow(data.max_depth));
+        }
+        data.depth += 1;
+        Ok(data.depth - 1)
+    }
+
+    // how many more steps the evaluation can take, none for no limit. a step is a form for the
+    // tree walker and the closures backend, a call for the bytecode vm.
+    pub fn set_step_budget(&self, steps: Option<u64>) {
+        self.data.borrow_mut().steps_left = steps;
+    }
+
+    pub fn steps_left(&self) -> Option<u64> {
+        self.data.borrow().steps_left
+    }
+
+    pub fn set_deadline(&self, deadline: Option<Instant>) {
+        let mut data = self.data.borrow_mut();
+        data.deadline = deadline;
+        data.steps_until_clock_check = 0;
+    }
+
+    // setting it to true, from another thread or a signal handler, stops the running evaluation
+    // with an Interrupted error at its next step
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        self.data.borrow().interrupt.clone()
+    }
+
+    // counts a step against the budget. both limits and interrupts abort the evaluation,
+    // try* doesn't catch it.
+    #[inline]
+    pub(super) fn step(&self) -> Result<(), EvalError> {
+        let data = &mut *self.data.borrow_mut();
+        if data.interrupt.load(Ordering::Relaxed) && data.interrupt.swap(false, Ordering::Relaxed) {
+            return Err(EvalError::Aborted(AbortReason::Interrupted));
+        }
+        match (data.steps_left, data.deadline) {
+            (None, None) => Ok(()),
+            _ => data.spend_step(),
+        }
+    }
+
+    pub(super) fn max_depth(&self) -> usize {
+        self.data.borrow().max_depth
+    }
+
+    pub(super) fn depth(&self) -> usize {
+        self.data.borrow().depth
+    }
+
+    pub(super) fn restore_depth(&self, depth: usize) {
+        self.data.borrow_mut().depth = depth;
+    }
+
+    pub(super) fn call_stack_len(&self) -> usize {
+        self.data.borrow().call_stack.len()
+    }
+
+    pub(super) fn truncate_call_stack(&self, stack_depth: usize) {
+        self.data.borrow_mut().call_stack.truncate(stack_depth);
+    }
+
+    pub(super) fn traced(&self, err: EvalError, span: Option<&Rc<SourceSpan>>) -> EvalError {
+        err.traced(span, &self.data.borrow().call_stack)
+    }
+
     pub fn set_trace(&self, flag: TraceFlag, enabled: bool) {
         if enabled {
             self.data.borrow_mut().trace_config.insert(flag);
@@ -198,12 +456,17 @@ impl Evaluator {
         }
     }
 
-    fn trace_lambda_funcall(&self, body: &AstNode, params_names: &Vec<String>, env: &Environment) {
+    pub(super) fn trace_lambda_funcall(
+        &self,
+        body: &AstNode,
+        params_names: &Vec<Symbol>,
+        env: &Environment,
+    ) {
         if self.is_tracing(&TraceFlag::TraceFnCalls) {
             println!("calling fn*:");
             println!("  params:");
             for n in params_names {
-                match env.find(n) {
+                match env.find(*n) {
                     Some(v) => match v.value() {
                         EnvironmentEntryValue::Value(val) => println!("    {} = {:?}", n, val),
                         EnvironmentEntryValue::NativeFunction(function) => {
@@ -218,3 +481,44 @@ impl Evaluator {
         }
     }
 }
+
+// the environment of a call to a lambda, with its parameters bound to the values
+pub(super) fn bind_params(
+    lambda: &LambdaEntry,
+    params_values: Vec<AstNode>,
+) -> Result<Environment, EvalError> {
+    let mut new_env = Environment::new_child(lambda.env.clone());
+    let mut params_names = lambda.params.iter();
+    let mut params_values = params_values.into_iter();
+    while let Some(name) = params_names.next() {
+        if name.name() == "&" {
+            // special case: now we bind all the remaining values to the last parameter
+            let name = match (params_names.next(), params_names.next()) {
+                (Some(name), None) => name,
+                // sanity check: exactly one parameter after this
+                _ => {
+                    return Err(EvalError::custom_exception_str(
+                        "& does not appear in penultimate position in the function parameters"
+                            .to_string(),
+                    ))
+                }
+            };
+            let value = AstNode::List(params_values.by_ref().collect(), None, None);
+            new_env.set_owned(EnvironmentEntry::new_ast_value(*name, value));
+            break;
+        }
+
+        let value = params_values.next().ok_or_else(|| {
+            EvalError::custom_exception_str(
+                "Not enough parameters supplied to lambda function call".to_string(),
+            )
+        })?;
+        new_env.set_owned(EnvironmentEntry::new_ast_value(*name, value));
+    }
+    if params_values.next().is_some() {
+        return Err(EvalError::custom_exception_str(
+            "Too many parameters passed to lambda call",
+        ));
+    }
+    Ok(new_env)
+}
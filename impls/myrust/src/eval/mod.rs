@@ -1,9 +1,20 @@
+mod bytecode;
+mod call_frame;
+mod compiler;
 mod errors;
 mod evaluator;
+mod resolver;
 
 pub use crate::environment::new_base_environment;
 pub use crate::environment::{
     Environment, EnvironmentEntry, EnvironmentEntryValue, SharedEnvironment,
 };
-pub use errors::EvalError;
+pub use bytecode::Closure;
+pub use call_frame::{CallFrame, StackTrace};
+pub use compiler::Code;
+pub use errors::{AbortReason, EvalError};
 pub use evaluator::*;
+pub use resolver::resolve;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,179 @@
+use im_rc::Vector;
+
+use crate::read::{AstNode, LexicalAddress, Symbol};
+
+// the analysis pass run on top level forms before evaluating them.
+// symbols referring to a variable of an enclosing fn*, let* or catch* get the address of its slot,
+// so that the evaluator doesn't look them up by name. globals are left as they are.
+pub fn resolve(ast: &AstNode) -> AstNode {
+    Resolver {
+        scopes: Scopes::default(),
+    }
+    .resolve(ast)
+}
+
+// the variables of the enclosing environments, innermost last. their position is their slot.
+#[derive(Clone, Default)]
+pub(super) struct Scopes(Vec<Scope>);
+
+#[derive(Clone, Default)]
+struct Scope {
+    variables: Vec<Symbol>,
+    // the names a let* binds after the value being resolved
+    pending: Vec<Symbol>,
+}
+
+impl Scopes {
+    pub(super) fn push(&mut self) {
+        self.0.push(Scope::default());
+    }
+
+    pub(super) fn pop(&mut self) {
+        self.0.pop();
+    }
+
+    // variables are set in order, and setting one again keeps its slot
+    pub(super) fn declare(&mut self, name: Symbol) {
+        let scope = &mut self.0.last_mut().unwrap().variables;
+        if !scope.contains(&name) {
+            scope.push(name);
+        }
+    }
+
+    // the names of a let*, before resolving its values. a closure in a value sees the ones bound
+    // after it once they are set, and before that the outer variables: their references keep the
+    // lookup by name instead of an address.
+    pub(super) fn declare_pending(&mut self, names: impl IntoIterator<Item = Symbol>) {
+        self.0.last_mut().unwrap().pending.extend(names);
+    }
+
+    // the parameters of a fn*, bound in order by the calls
+    pub(super) fn declare_params(&mut self, params: &Vector<AstNode>) {
+        for param in params {
+            match param {
+                AstNode::UnresolvedSymbol(name, _) if name.name() != "&" => self.declare(*name),
+                _ => {}
+            }
+        }
+    }
+
+    pub(super) fn address_of(&self, name: Symbol) -> Option<LexicalAddress> {
+        for (depth, scope) in self.0.iter().rev().enumerate() {
+            if let Some(slot) = scope
+                .variables
+                .iter()
+                .position(|variable| *variable == name)
+            {
+                return Some(LexicalAddress { depth, slot });
+            }
+            if scope.pending.contains(&name) {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+struct Resolver {
+    scopes: Scopes,
+}
+
+impl Resolver {
+    fn resolve(&mut self, ast: &AstNode) -> AstNode {
+        match ast {
+            AstNode::UnresolvedSymbol(name, _) => {
+                AstNode::UnresolvedSymbol(*name, self.scopes.address_of(*name))
+            }
+            AstNode::List(elements, span, meta) => {
+                AstNode::List(self.resolve_form(elements), span.clone(), meta.clone())
+            }
+            AstNode::Vector(elements, meta) => {
+                AstNode::Vector(self.resolve_all(elements), meta.clone())
+            }
+            AstNode::HashMap(hm, meta) => AstNode::HashMap(
+                hm.iter()
+                    .map(|(key, value)| (key.clone(), self.resolve(value)))
+                    .collect(),
+                meta.clone(),
+            ),
+            // map keys and set elements are rarely variables, they keep the lookup by name
+            any => any.clone(),
+        }
+    }
+
+    fn resolve_all(&mut self, elements: &Vector<AstNode>) -> Vector<AstNode> {
+        elements
+            .iter()
+            .map(|element| self.resolve(element))
+            .collect()
+    }
+
+    // the special forms creating environments, with the same checks as the natives.
+    // invalid forms are resolved as function calls, they fail when evaluated anyway.
+    fn resolve_form(&mut self, elements: &Vector<AstNode>) -> Vector<AstNode> {
+        let head = match elements.front() {
+            Some(AstNode::UnresolvedSymbol(name, _)) => name.name(),
+            _ => "",
+        };
+        let mut elements = elements.clone();
+        match (head, elements.len()) {
+            ("quote", _) => elements,
+            ("fn*", 3) => {
+                let params = match &elements[1] {
+                    AstNode::List(params, _, _) | AstNode::Vector(params, _) => params,
+                    _ => return self.resolve_all(&elements),
+                };
+                self.scopes.push();
+                self.scopes.declare_params(params);
+                let body = self.resolve(&elements[2]);
+                elements.set(2, body);
+                self.scopes.pop();
+                elements
+            }
+            ("let*", 3) => {
+                self.scopes.push();
+                if let AstNode::List(bindings, _, _) | AstNode::Vector(bindings, _) =
+                    &mut elements[1]
+                {
+                    // each value sees the variables bound before it
+                    let names = bindings.iter().step_by(2).filter_map(|name| match name {
+                        AstNode::UnresolvedSymbol(name, _) => Some(*name),
+                        _ => None,
+                    });
+                    self.scopes.declare_pending(names);
+                    for index in (0..bindings.len()).step_by(2) {
+                        if index + 1 < bindings.len() {
+                            let value = self.resolve(&bindings[index + 1]);
+                            bindings.set(index + 1, value);
+                        }
+                        if let AstNode::UnresolvedSymbol(name, _) = bindings[index] {
+                            self.scopes.declare(name);
+                        }
+                    }
+                }
+                let body = self.resolve(&elements[2]);
+                elements.set(2, body);
+                self.scopes.pop();
+                elements
+            }
+            ("try*", 3) => {
+                let body = self.resolve(&elements[1]);
+                elements.set(1, body);
+                if let AstNode::List(clause, _, _) | AstNode::Vector(clause, _) = &mut elements[2] {
+                    if let (3, Some(AstNode::UnresolvedSymbol(name, _))) =
+                        (clause.len(), clause.get(1))
+                    {
+                        self.scopes.push();
+                        self.scopes.declare(*name);
+                        self.scopes.declare(Symbol::new("*stack-trace*"));
+                        let handler = self.resolve(&clause[2]);
+                        clause.set(2, handler);
+                        self.scopes.pop();
+                    }
+                }
+                elements
+            }
+            _ => self.resolve_all(&elements),
+        }
+    }
+}
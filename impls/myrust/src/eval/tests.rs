@@ -0,0 +1,120 @@
+use super::{new_base_environment, resolve, Backend, Evaluator, DEFAULT_MAX_DEPTH};
+use crate::read::{InputReader, Lexer, Parser, StringInputSource};
+
+const BACKENDS: [Backend; 3] = [Backend::TreeWalker, Backend::Closures, Backend::Bytecode];
+
+// each form with what it evaluates to, or "error: " and the value a catch* gets.
+// the forms run in order in the same environment.
+const CASES: &[(&str, &str)] = &[
+    ("(+ 1 2)", "3"),
+    ("(* 4294967296 4294967296)", "18446744073709551616"),
+    // upvalues: every closure made in a loop keeps its own iteration's binding
+    (
+        "(def! make (fn* (n acc) (if (= n 0) acc (make (- n 1) (cons (fn* () n) acc)))))",
+        "#<function>",
+    ),
+    ("(map (fn* (f) (f)) (make 3 ()))", "(1 2 3)"),
+    (
+        "(map (fn* (f) (f)) (map (fn* (i) (let* (j (* i 10)) (fn* () (+ i j)))) (list 1 2 3)))",
+        "(11 22 33)",
+    ),
+    ("((((fn* (a) (fn* (b) (fn* (c) (list a b c)))) 1) 2) 3)", "(1 2 3)"),
+    (
+        "(let* (counter (let* (a (atom 0)) (fn* () (swap! a + 1)))) (do (counter) (counter) (counter)))",
+        "3",
+    ),
+    // shadowing in let*, fn* and try*
+    ("(let* (x 1 y (let* (x 2) x) x (+ x y)) (list x y))", "(3 2)"),
+    ("(def! x 100)", "100"),
+    ("(let* (x 1) ((fn* (x) (+ x 1)) 5))", "6"),
+    ("(let* (f (fn* () x)) (let* (x 2) (f)))", "100"),
+    ("((fn* (x) (let* (x (* x 2)) ((fn* () x)))) 4)", "8"),
+    ("(let* (x 1) (let* (g (fn* () x) x 2) (g)))", "2"),
+    ("(let* (x 1) (let* (g (fn* () x) y 0 x 2) (g)))", "2"),
+    ("(let* (x 1) (let* (y x x 2) (list x y)))", "(2 1)"),
+    ("((fn* (a & rest) (list a rest)) 1 2 3)", "(1 (2 3))"),
+    ("(let* (e 1) (list (try* (throw 2) (catch* e e)) e))", "(2 1)"),
+    ("((try* (throw 7) (catch* e (fn* () e))))", "7"),
+    ("(let* (if (fn* (a b) b)) (if 1 2))", "2"),
+    // macros and eval
+    ("(defmacro! unless (fn* (c a b) `(if ~c ~b ~a)))", "#<function>"),
+    ("(unless false 1 2)", "1"),
+    ("(let* (x 1) (eval '(+ x 2)))", "102"),
+    // errors
+    ("(throw [:oops 1])", "error: [:oops 1]"),
+    ("(nosuch 1)", "error: \"'nosuch' not found\""),
+    ("(+ 1 \"a\")", "error: (:TypeError \"Number\" \"a\")"),
+    ("(try* (nth (list 1) 5) (catch* e :caught))", ":caught"),
+    ("(def! g (fn* (x) (let* (y (throw x)) y)))", "#<function>"),
+    ("(def! f (fn* (x) (do (g x) 1)))", "#<function>"),
+    ("(f :deep)", "error: :deep"),
+    (
+        "(try* (f 1) (catch* e (map (fn* (frame) (get frame :name)) *stack-trace*)))",
+        "(\"try*\" \"f\" \"g\" \"throw\")",
+    ),
+    // tail calls don't grow the stack
+    (
+        "(def! count-down (fn* (n) (if (= n 0) :done (count-down (- n 1)))))",
+        "#<function>",
+    ),
+    ("(count-down 20000)", ":done"),
+];
+
+// what each form gave, and the stack trace of the errors
+fn run_cases(backend: Backend) -> Vec<(String, Vec<String>)> {
+    let evaluator = Evaluator::new();
+    evaluator.set_backend(backend);
+    let environment = new_base_environment();
+    crate::load_startup(&evaluator, &environment).unwrap();
+    CASES
+        .iter()
+        .map(|(form, _)| {
+            let mut input = InputReader::new(Box::new(StringInputSource::new(form.to_string())))
+                .with_file_name("test");
+            let lexer = Lexer::create_lexer_iterator(&mut input);
+            let ast = Parser::new(lexer).read_form(true).unwrap();
+            match evaluator.eval(resolve(&ast), environment.clone()) {
+                Ok(value) => (value.to_string(), vec![]),
+                Err(err) => {
+                    let frames = err
+                        .stack_trace()
+                        .frames()
+                        .iter()
+                        .map(|frame| frame.to_string())
+                        .collect();
+                    if err.is_catchable() {
+                        (format!("error: {}", err.caught().0), frames)
+                    } else {
+                        (format!("error: {}", err), frames)
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn backends_agree() {
+    let results: Vec<_> = BACKENDS
+        .iter()
+        .map(|backend| {
+            let backend = *backend;
+            // the same room for nested evaluations as the main thread
+            std::thread::Builder::new()
+                .stack_size((DEFAULT_MAX_DEPTH + 1) * crate::STACK_PER_DEPTH)
+                .spawn(move || run_cases(backend))
+                .unwrap()
+                .join()
+                .unwrap()
+        })
+        .collect();
+    for (index, (form, expected)) in CASES.iter().enumerate() {
+        let (tree_value, tree_trace) = &results[0][index];
+        assert_eq!(tree_value, expected, "{}", form);
+        for (backend, result) in BACKENDS.iter().zip(&results).skip(1) {
+            let (value, trace) = &result[index];
+            assert_eq!(value, expected, "{} on {:?}", form, backend);
+            assert_eq!(trace, tree_trace, "{} on {:?}", form, backend);
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use std::{cell::RefCell, rc::Rc};
 
+use im_rc::vector;
+
 use crate::read::AstNode;
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![
@@ -24,6 +28,10 @@ impl NativeFunction for AtomFn {
         "atom".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
         Ok(FunctionCallResultSuccess::Value(AstNode::Atom(Rc::new(
@@ -42,6 +50,10 @@ impl NativeFunction for IsAtomFn {
         "atom?".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
         Ok(FunctionCallResultSuccess::Value(AstNode::Bool(
@@ -60,6 +72,10 @@ impl NativeFunction for DerefFn {
         "deref".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
         Ok(FunctionCallResultSuccess::Value(
@@ -83,6 +99,10 @@ impl NativeFunction for ResetBangFn {
         "reset!".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(2))?;
         let mut ast = data.destructure().0;
@@ -104,6 +124,10 @@ impl NativeFunction for SwapBangFn {
         "swap!".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::at_least(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), None)?;
         let evaluator = data.evaluator();
@@ -114,20 +138,21 @@ impl NativeFunction for SwapBangFn {
 
         let mut ast: Vec<AstNode> = ast
             .into_iter()
-            .map(|parm| AstNode::List(vec![AstNode::UnresolvedSymbol("quote".to_string()), parm]))
+            .map(|parm| AstNode::List(vector![AstNode::create_symbol("quote"), parm], None, None))
             .collect();
 
         let atom_value: AstNode = atom.borrow().clone();
         ast.insert(
             0,
-            AstNode::List(vec![
-                AstNode::UnresolvedSymbol("quote".to_string()),
-                atom_value,
-            ]),
+            AstNode::List(
+                vector![AstNode::create_symbol("quote"), atom_value],
+                None,
+                None,
+            ),
         );
         ast.insert(0, function);
 
-        let new_value = evaluator.eval(AstNode::List(ast), env)?; // (<function> <atom_value> <rest...>)
+        let new_value = evaluator.eval(AstNode::List(ast.into(), None, None), env)?; // (<function> <atom_value> <rest...>)
         atom.replace(new_value.clone());
 
         Ok(FunctionCallResultSuccess::Value(new_value))
@@ -1,8 +1,11 @@
 use std::rc::Rc;
 
-use crate::read::AstNode;
+use crate::{eval::EvalError, read::AstNode};
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    math::compare_numbers, Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess,
+    NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![
@@ -30,15 +33,18 @@ impl NativeFunction for EqualOp {
         "=".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::at_least(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
-        data.check_parameters_count_range(Some(2), Some(2))?;
+        data.check_parameters_count_range(Some(1), None)?;
 
-        let (mut params, _env) = data.destructure();
+        let (params, _env) = data.destructure();
 
-        let a = params.remove(0);
-        let b = params.remove(0);
+        let all_equal = params.windows(2).all(|pair| pair[0] == pair[1]);
 
-        return Ok(FunctionCallResultSuccess::Value(AstNode::Bool(a == b)));
+        return Ok(FunctionCallResultSuccess::Value(AstNode::Bool(all_equal)));
     }
 }
 
@@ -63,21 +69,39 @@ impl NativeFunction for NumComparisonOp {
         .to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::at_least(1)
+    }
+
+    // chained: (< a b c) is true when a < b and b < c
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
-        data.check_parameters_count_range(Some(2), Some(2))?;
+        data.check_parameters_count_range(Some(1), None)?;
 
-        let (mut params, _env) = data.destructure();
+        let (params, _env) = data.destructure();
 
-        let a = params.remove(0).try_unwrap_int()?;
-        let b = params.remove(0).try_unwrap_int()?;
-        let result = match self {
-            NumComparisonOp::Gt => a > b,
-            NumComparisonOp::Lt => a < b,
-            NumComparisonOp::Le => a <= b,
-            NumComparisonOp::Ge => a >= b,
-        };
+        let mut params = params.into_iter();
+        let mut previous = params.next().unwrap();
+        for next in params {
+            if !self.compare(previous, next.clone())? {
+                return Ok(FunctionCallResultSuccess::Value(AstNode::Bool(false)));
+            }
+            previous = next;
+        }
 
-        return Ok(FunctionCallResultSuccess::Value(AstNode::Bool(result)));
+        return Ok(FunctionCallResultSuccess::Value(AstNode::Bool(true)));
+    }
+}
+
+impl NumComparisonOp {
+    fn compare(&self, a: AstNode, b: AstNode) -> Result<bool, EvalError> {
+        // comparisons with NaN are always false
+        Ok(match (self, compare_numbers(a, b)?) {
+            (_, None) => false,
+            (NumComparisonOp::Gt, Some(ord)) => ord.is_gt(),
+            (NumComparisonOp::Lt, Some(ord)) => ord.is_lt(),
+            (NumComparisonOp::Le, Some(ord)) => ord.is_le(),
+            (NumComparisonOp::Ge, Some(ord)) => ord.is_ge(),
+        })
     }
 }
 
@@ -104,6 +128,10 @@ impl NativeFunction for BooleanBinaryOp {
         .to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(2))?;
 
@@ -134,6 +162,10 @@ impl NativeFunction for NotOp {
         "not".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
@@ -2,7 +2,9 @@ use std::rc::Rc;
 
 use crate::read::AstNode;
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![Rc::new(If), Rc::new(Do)]
@@ -18,6 +20,10 @@ impl NativeFunction for If {
         "if".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::between(2, 3)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(3))?;
 
@@ -55,6 +61,10 @@ impl NativeFunction for Do {
         "do".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::at_least(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         let (mut params, env) = data.destructure();
 
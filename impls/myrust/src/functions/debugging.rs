@@ -1,13 +1,19 @@
 use std::rc::Rc;
 
-use crate::{eval::TraceFlag, read::AstNode};
+use crate::{
+    eval::{CompiledBody, EvalError, TraceFlag},
+    read::AstNode,
+};
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![
         Rc::new(SetTraceFlagFn::new(TraceFlag::TraceNativeFunctionCalls)),
         Rc::new(SetTraceFlagFn::new(TraceFlag::TraceFnCalls)),
+        Rc::new(DisassembleFn),
     ]
 }
 
@@ -33,6 +39,10 @@ impl NativeFunction for SetTraceFlagFn {
             }
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
         let evaluator = data.evaluator();
@@ -43,3 +53,40 @@ impl NativeFunction for SetTraceFlagFn {
         Ok(FunctionCallResultSuccess::Value(AstNode::Nil))
     }
 }
+
+struct DisassembleFn;
+impl NativeFunction for DisassembleFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "disassemble".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
+    // prints the bytecode of a function, and of the functions it creates
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), Some(1))?;
+        let (mut ast, _env) = data.destructure();
+
+        match ast.remove(0) {
+            AstNode::Lambda(lambda, _) => match &lambda.code {
+                Some(CompiledBody::Bytecode(closure)) => {
+                    print!("{}", closure.disassemble());
+                    Ok(FunctionCallResultSuccess::Value(AstNode::Nil))
+                }
+                _ => Err(EvalError::custom_exception_str(
+                    "disassemble needs a function compiled with --backend=bytecode",
+                )),
+            },
+            x => Err(EvalError::TypeError {
+                expected: "fn* lambda".to_string(),
+                got: x,
+            }),
+        }
+    }
+}
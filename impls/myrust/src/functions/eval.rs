@@ -1,13 +1,18 @@
 use std::rc::Rc;
 
-use crate::eval::EvalError;
+use crate::eval::{resolve, EvalError};
 
 use super::{
-    FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction, TailCallData,
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+    TailCallData,
 };
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
-    vec![Rc::new(EvalFn), Rc::new(ReadStringFn)]
+    vec![
+        Rc::new(EvalFn),
+        Rc::new(ReadStringFn),
+        Rc::new(WithBudgetFn),
+    ]
 }
 
 struct EvalFn;
@@ -20,12 +25,16 @@ impl NativeFunction for EvalFn {
         "eval".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
         let (mut ast, env) = data.destructure();
 
         Ok(FunctionCallResultSuccess::TailCall(TailCallData::new(
-            ast.remove(0),
+            resolve(&ast.remove(0)),
             env.get_root(),
         )))
     }
@@ -41,6 +50,10 @@ impl NativeFunction for ReadStringFn {
         "read-string".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         use crate::read::{InputReader, Lexer, Parser, StringInputSource};
 
@@ -61,3 +74,44 @@ impl NativeFunction for ReadStringFn {
         }
     }
 }
+
+struct WithBudgetFn;
+impl NativeFunction for WithBudgetFn {
+    fn evaluates_arguments(&self) -> bool {
+        false
+    }
+
+    fn name(&self) -> String {
+        "with-budget".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
+    // (with-budget n expr) evaluates expr in at most n steps, within the budget already set if
+    // there's one. running out aborts the whole evaluation, try* doesn't catch it.
+    // steps are counted like for --max-steps, see main.rs.
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(2), Some(2))?;
+        let evaluator = data.evaluator();
+        let (mut ast, env) = data.destructure();
+
+        let steps = evaluator
+            .eval(ast.remove(0), env.clone())?
+            .try_unwrap_int()?;
+        if steps < 0 {
+            return Err(EvalError::custom_exception_str(
+                "with-budget needs a number of steps >= 0",
+            ));
+        }
+        let outer = evaluator.steps_left();
+        let steps = outer.map_or(steps as u64, |outer| outer.min(steps as u64));
+        evaluator.set_step_budget(Some(steps));
+        let result = evaluator.eval(ast.remove(0), env);
+        // the steps taken are spent from the enclosing budget too
+        let used = steps - evaluator.steps_left().unwrap_or(0);
+        evaluator.set_step_budget(outer.map(|outer| outer - used));
+        Ok(FunctionCallResultSuccess::Value(result?))
+    }
+}
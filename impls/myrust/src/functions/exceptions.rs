@@ -2,11 +2,12 @@ use std::rc::Rc;
 
 use crate::{
     eval::{Environment, EnvironmentEntry, EvalError},
-    read::AstNode,
+    read::Symbol,
 };
 
 use super::{
-    FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction, TailCallData,
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+    TailCallData,
 };
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
@@ -23,6 +24,10 @@ impl NativeFunction for TryStarFn {
         "try*".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(2))?;
         let evaluator = data.evaluator();
@@ -34,33 +39,15 @@ impl NativeFunction for TryStarFn {
         let result = evaluator.eval(to_run, env.clone());
 
         if let Err(x) = result {
-            if catch_clause.remove(0).try_unwrap_symbol()? != "catch*" {
+            if !x.is_catchable() {
+                return Err(x);
+            }
+            if catch_clause.remove(0).try_unwrap_symbol()?.name() != "catch*" {
                 return Err(EvalError::custom_exception_str(
                     "The second parameter of try* should be starting with (catch*)",
                 ));
             }
-            let exception_data = match x {
-                EvalError::SymbolNotFound(symbol) => {
-                    AstNode::String(format!("\'{}\' not found", symbol))
-                }
-                EvalError::InvalidFunctionCallNodeType(node) => AstNode::List(vec![
-                    AstNode::create_keyword("InvalidFunctionCallNodeType"),
-                    node,
-                ]),
-                EvalError::ParameterCountError {
-                    expected_min: _,
-                    expected_max: _,
-                    provided: _,
-                } => AstNode::List(vec![
-                    AstNode::create_keyword("ParameterCount"), /* it would be great to provide more parameters, but whatever */
-                ]),
-                EvalError::TypeError { expected, got } => AstNode::List(vec![
-                    AstNode::create_keyword("TypeError"),
-                    AstNode::String(expected),
-                    got,
-                ]),
-                EvalError::CustomException(custom) => custom,
-            };
+            let (exception_data, stack_trace) = x.caught();
 
             let name = catch_clause.remove(0).try_unwrap_symbol()?;
             let handler = catch_clause.remove(0);
@@ -69,6 +56,10 @@ impl NativeFunction for TryStarFn {
                 name,
                 exception_data,
             )));
+            handler_env.set(Rc::new(EnvironmentEntry::new_ast_value(
+                Symbol::new("*stack-trace*"),
+                stack_trace,
+            )));
 
             Ok(FunctionCallResultSuccess::TailCall(TailCallData::new(
                 handler,
@@ -90,6 +81,10 @@ impl NativeFunction for ThrowFn {
         "throw".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
         let (mut ast, _env) = data.destructure();
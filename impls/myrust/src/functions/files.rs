@@ -2,7 +2,9 @@ use std::rc::Rc;
 
 use crate::read::AstNode;
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![Rc::new(SlurpFn)]
@@ -18,6 +20,10 @@ impl NativeFunction for SlurpFn {
         "slurp".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
         let (mut ast, _env) = data.destructure();
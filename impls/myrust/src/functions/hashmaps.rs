@@ -1,8 +1,13 @@
 use std::rc::Rc;
 
+use im_rc::vector;
+
 use crate::{eval::EvalError, read::AstNode};
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::sorted::comparator_call;
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![
@@ -26,13 +31,17 @@ impl NativeFunction for IsMapFn {
         "map?".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
-        let ast = data.destructure().0.remove(0);
-        Ok(FunctionCallResultSuccess::Value(AstNode::Bool(
-            ast.try_unwrap_hashmap().is_ok(),
-        )))
+        Ok(FunctionCallResultSuccess::Value(AstNode::Bool(matches!(
+            data.destructure().0.remove(0),
+            AstNode::HashMap(_, _) | AstNode::SortedMap(_, _)
+        ))))
     }
 }
 
@@ -46,10 +55,15 @@ impl NativeFunction for AssocFn {
         "assoc".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::at_least(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), None)?;
 
-        let mut ast = data.destructure().0;
+        let evaluator = data.evaluator();
+        let (mut ast, env) = data.destructure();
 
         let hashmap = ast.remove(0);
         if hashmap == AstNode::Nil {
@@ -57,20 +71,35 @@ impl NativeFunction for AssocFn {
             // this behavior was completely undocumented in the specs :(
             return Ok(FunctionCallResultSuccess::Value(AstNode::Nil));
         }
-        let mut hashmap = hashmap.try_unwrap_hashmap()?;
 
+        let mut entries = vec![];
         while ast.len() != 0 {
             if ast.len() == 1 {
                 return Err(EvalError::custom_exception_str(
                     "Expected an even number of parameters for hash-map",
                 ));
             }
-            let key = ast.remove(0).try_unwrap_string()?;
+            let key = ast.remove(0).try_unwrap_hashmap_key()?;
             let value = ast.remove(0);
-            hashmap.insert(key, value);
+            entries.push((key, value));
         }
 
-        Ok(FunctionCallResultSuccess::Value(AstNode::HashMap(hashmap)))
+        if let AstNode::SortedMap(mut map, meta) = hashmap {
+            let mut call = comparator_call(evaluator, env);
+            for (key, value) in entries {
+                map.insert(key, value, &mut call)?;
+            }
+            return Ok(FunctionCallResultSuccess::Value(AstNode::SortedMap(
+                map, meta,
+            )));
+        }
+
+        let meta = hashmap.meta();
+        let mut hashmap = hashmap.try_unwrap_hashmap()?;
+        hashmap.extend(entries);
+        Ok(FunctionCallResultSuccess::Value(AstNode::HashMap(
+            hashmap, meta,
+        )))
     }
 }
 
@@ -84,10 +113,15 @@ impl NativeFunction for DissocFn {
         "dissoc".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::at_least(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), None)?;
 
-        let mut ast = data.destructure().0;
+        let evaluator = data.evaluator();
+        let (mut ast, env) = data.destructure();
 
         let hashmap = ast.remove(0);
         if hashmap == AstNode::Nil {
@@ -95,14 +129,28 @@ impl NativeFunction for DissocFn {
             // this behavior was completely undocumented in the specs :(
             return Ok(FunctionCallResultSuccess::Value(AstNode::Nil));
         }
+
+        if let AstNode::SortedMap(mut map, meta) = hashmap {
+            let mut call = comparator_call(evaluator, env);
+            for key in ast {
+                map.remove(&key.try_unwrap_hashmap_key()?, &mut call)?;
+            }
+            return Ok(FunctionCallResultSuccess::Value(AstNode::SortedMap(
+                map, meta,
+            )));
+        }
+
+        let meta = hashmap.meta();
         let mut hashmap = hashmap.try_unwrap_hashmap()?;
 
         while ast.len() != 0 {
-            let key = ast.remove(0).try_unwrap_string()?;
+            let key = ast.remove(0).try_unwrap_hashmap_key()?;
             hashmap.remove(&key) /* ignore missing values */;
         }
 
-        Ok(FunctionCallResultSuccess::Value(AstNode::HashMap(hashmap)))
+        Ok(FunctionCallResultSuccess::Value(AstNode::HashMap(
+            hashmap, meta,
+        )))
     }
 }
 
@@ -116,10 +164,15 @@ impl NativeFunction for GetFn {
         "get".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(2))?;
 
-        let mut ast = data.destructure().0;
+        let evaluator = data.evaluator();
+        let (mut ast, env) = data.destructure();
 
         let hashmap = ast.remove(0);
         if hashmap == AstNode::Nil {
@@ -127,11 +180,16 @@ impl NativeFunction for GetFn {
             // this behavior was completely undocumented in the specs :(
             return Ok(FunctionCallResultSuccess::Value(AstNode::Nil));
         }
-        let mut hashmap = hashmap.try_unwrap_hashmap()?;
 
-        let key = ast.remove(0).try_unwrap_string()?;
+        let key = ast.remove(0).try_unwrap_hashmap_key()?;
 
-        let value = hashmap.remove(&key).unwrap_or(AstNode::Nil);
+        let value = match hashmap {
+            AstNode::SortedMap(map, _) => map
+                .get(&key, &mut comparator_call(evaluator, env))?
+                .cloned(),
+            x => x.try_unwrap_hashmap()?.remove(&key),
+        }
+        .unwrap_or(AstNode::Nil);
 
         Ok(FunctionCallResultSuccess::Value(value))
     }
@@ -147,23 +205,33 @@ impl NativeFunction for ContainsFn {
         "contains?".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(2))?;
 
-        let mut ast = data.destructure().0;
+        let evaluator = data.evaluator();
+        let (mut ast, env) = data.destructure();
 
         let hashmap = ast.remove(0);
         if hashmap == AstNode::Nil {
             // be consistent with the behavior of get - never raise errors when hashmap is nil
             return Ok(FunctionCallResultSuccess::Value(AstNode::Bool(false)));
         }
-        let hashmap = hashmap.try_unwrap_hashmap()?;
 
-        let key = ast.remove(0).try_unwrap_string()?;
-
-        Ok(FunctionCallResultSuccess::Value(AstNode::Bool(
-            hashmap.contains_key(&key),
-        )))
+        let key = ast.remove(0).try_unwrap_hashmap_key()?;
+        let mut call = comparator_call(evaluator, env);
+
+        // works both on hash-maps keys and set elements
+        let contained = match hashmap {
+            AstNode::Set(set, _) => set.contains(&key),
+            AstNode::SortedSet(set, _) => set.get(&key, &mut call)?.is_some(),
+            AstNode::SortedMap(map, _) => map.get(&key, &mut call)?.is_some(),
+            x => x.try_unwrap_hashmap()?.contains_key(&key),
+        };
+        Ok(FunctionCallResultSuccess::Value(AstNode::Bool(contained)))
     }
 }
 
@@ -184,6 +252,10 @@ impl NativeFunction for KeysValsFn {
         .to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
@@ -192,18 +264,28 @@ impl NativeFunction for KeysValsFn {
         let hashmap = ast.remove(0);
         if hashmap == AstNode::Nil {
             // be consistent with the behavior of get - never raise errors when hashmap is nil
-            return Ok(FunctionCallResultSuccess::Value(AstNode::List(vec![])));
+            return Ok(FunctionCallResultSuccess::Value(AstNode::List(
+                vector![],
+                None,
+                None,
+            )));
         }
-        let hashmap = hashmap.try_unwrap_hashmap()?;
+        // sorted maps return the keys in order
+        let entries = match hashmap {
+            AstNode::SortedMap(map, _) => map.into_entries(),
+            x => x.try_unwrap_hashmap()?.into_iter().collect(),
+        };
 
         Ok(FunctionCallResultSuccess::Value(AstNode::List(
-            hashmap
+            entries
                 .into_iter()
                 .map(|kv| match self {
-                    Self::Keys => AstNode::String(kv.0),
+                    Self::Keys => kv.0.into_ast_node(),
                     Self::Vals => kv.1,
                 })
                 .collect(),
+            None,
+            None,
         )))
     }
 }
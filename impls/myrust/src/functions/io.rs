@@ -1,16 +1,25 @@
 // in addition to i/o functions, it contains other "platform" routines like time management, metadata association,
 
-use std::{io::Write, rc::Rc};
+use std::{
+    io::Write,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::read::AstNode;
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![
         Rc::new(ReadLineFn),
         Rc::new(HostLanguageFn),
         Rc::new(ArgvFn),
+        Rc::new(TimeMsFn),
+        Rc::new(MetaFn),
+        Rc::new(WithMetaFn),
     ]
 }
 
@@ -24,6 +33,10 @@ impl NativeFunction for ReadLineFn {
         "readline".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::between(0, 1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(0), Some(1))?;
         let mut args = data.destructure().0;
@@ -49,6 +62,10 @@ impl NativeFunction for HostLanguageFn {
         "*host-language*".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(0)
+    }
+
     fn run(&self, data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(0), Some(0))?;
         Ok(FunctionCallResultSuccess::Value(AstNode::String(
@@ -67,10 +84,91 @@ impl NativeFunction for ArgvFn {
         "get-argv".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(0)
+    }
+
     fn run(&self, data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(0), Some(0))?;
 
         let args = std::env::args().map(|arg| AstNode::String(arg)).collect();
-        Ok(FunctionCallResultSuccess::Value(AstNode::List(args)))
+        Ok(FunctionCallResultSuccess::Value(AstNode::List(
+            args, None, None,
+        )))
+    }
+}
+
+struct TimeMsFn;
+impl NativeFunction for TimeMsFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "time-ms".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(0)
+    }
+
+    fn run(&self, data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(0), Some(0))?;
+
+        let elapsed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as i64)
+            .unwrap_or(0);
+        Ok(FunctionCallResultSuccess::Value(AstNode::Int(elapsed)))
+    }
+}
+
+struct MetaFn;
+impl NativeFunction for MetaFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "meta".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), Some(1))?;
+
+        let meta = data.destructure().0.remove(0).meta();
+        Ok(FunctionCallResultSuccess::Value(match meta {
+            Some(meta) => (*meta).clone(),
+            None => AstNode::Nil,
+        }))
+    }
+}
+
+struct WithMetaFn;
+impl NativeFunction for WithMetaFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "with-meta".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
+    // returns a copy of the value with the new metadata. the original value is left untouched.
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(2), Some(2))?;
+
+        let mut args = data.destructure().0;
+        let value = args.remove(0);
+        let meta = Some(Rc::new(args.remove(0)));
+        Ok(FunctionCallResultSuccess::Value(value.with_meta(meta)?))
     }
 }
@@ -2,7 +2,9 @@ use std::rc::Rc;
 
 use crate::read::{AstNode, LambdaEntry};
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![Rc::new(FnStarFn), Rc::new(IsFnFn)]
@@ -18,6 +20,10 @@ impl NativeFunction for FnStarFn {
         "fn*".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(2))?;
 
@@ -32,15 +38,17 @@ impl NativeFunction for FnStarFn {
         }
 
         let lambda = LambdaEntry {
-            body: lambda_body,
+            body: Rc::new(lambda_body),
             params: params_as_strings,
             is_macro: false,
             env: env.clone(),
+            code: None,
         };
 
-        return Ok(FunctionCallResultSuccess::Value(AstNode::Lambda(Rc::new(
-            lambda,
-        ))));
+        return Ok(FunctionCallResultSuccess::Value(AstNode::Lambda(
+            Rc::new(lambda),
+            None,
+        )));
     }
 }
 
@@ -54,14 +62,18 @@ impl NativeFunction for IsFnFn {
         "fn?".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
         let node = data.destructure().0.remove(0);
 
         let is_lambda = match node {
-            AstNode::Lambda(_) => true,
-            AstNode::FunctionPtr(_) => true,
+            AstNode::Lambda(_, _) => true,
+            AstNode::FunctionPtr(_, _) => true,
             _ => false,
         };
         return Ok(FunctionCallResultSuccess::Value(AstNode::Bool(is_lambda)));
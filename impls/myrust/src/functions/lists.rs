@@ -1,8 +1,16 @@
 use std::rc::Rc;
 
-use crate::{eval::EvalError, read::AstNode};
+use im_rc::{vector, Vector};
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use crate::{
+    eval::EvalError,
+    read::{AstNode, HashMapKey},
+};
+
+use super::sorted::comparator_call;
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![
@@ -15,6 +23,8 @@ pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
         Rc::new(ConcatFn),
         Rc::new(VecFn),
         Rc::new(IsVectorFn),
+        Rc::new(SeqFn),
+        Rc::new(ConjFn),
     ]
 }
 
@@ -28,9 +38,15 @@ impl NativeFunction for ListFn {
         "list".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::at_least(0)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         Ok(FunctionCallResultSuccess::Value(AstNode::List(
-            data.destructure().0,
+            data.destructure().0.into(),
+            None,
+            None,
         )))
     }
 }
@@ -45,6 +61,10 @@ impl NativeFunction for IsListFn {
         "list?".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
@@ -64,6 +84,10 @@ impl NativeFunction for IsVectorFn {
         "vector?".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
@@ -83,17 +107,22 @@ impl NativeFunction for CountFn {
         "count".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
-        Ok(FunctionCallResultSuccess::Value(AstNode::Int(
-            data.destructure()
-                .0
-                .remove(0)
-                .try_unwrap_list_or_vector()
-                .map(|l| l.len())
-                .unwrap_or(0) as i64,
-        )))
+        let count = match data.destructure().0.remove(0) {
+            AstNode::List(l, _, _) | AstNode::Vector(l, _) => l.len(),
+            AstNode::HashMap(hm, _) => hm.len(),
+            AstNode::Set(set, _) => set.len(),
+            AstNode::SortedMap(map, _) => map.len(),
+            AstNode::SortedSet(set, _) => set.len(),
+            _ => 0,
+        };
+        Ok(FunctionCallResultSuccess::Value(AstNode::Int(count as i64)))
     }
 }
 struct NthFn;
@@ -106,6 +135,10 @@ impl NativeFunction for NthFn {
         "nth".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(2))?;
 
@@ -130,32 +163,20 @@ impl NativeFunction for RestFn {
         "rest".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
         let (mut ast, _env) = data.destructure();
 
-        let res = match ast.remove(0) {
-            AstNode::List(mut l) if l.len() > 0 => {
-                l.remove(0);
-                l
-            }
-            AstNode::Vector(mut l) if l.len() > 0 => {
-                l.remove(0);
-                l
-            }
-
-            AstNode::List(_) => vec![],
-            AstNode::Vector(_) => vec![],
-            AstNode::Nil => vec![],
-            x => {
-                return Err(EvalError::TypeError {
-                    expected: "List/Vector/Nil".to_string(),
-                    got: x,
-                })
-            }
-        };
-        Ok(FunctionCallResultSuccess::Value(AstNode::List(res)))
+        let mut res = seq_elements(ast.remove(0))?;
+        res.pop_front();
+        Ok(FunctionCallResultSuccess::Value(AstNode::List(
+            res, None, None,
+        )))
     }
 }
 
@@ -169,14 +190,20 @@ impl NativeFunction for ConsFn {
         "cons".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(2))?;
         let mut ast = data.destructure().0;
         let value = ast.remove(0);
         let mut list = ast.remove(0).try_unwrap_list_or_vector()?;
-        list.insert(0, value);
+        list.push_front(value);
 
-        Ok(FunctionCallResultSuccess::Value(AstNode::List(list)))
+        Ok(FunctionCallResultSuccess::Value(AstNode::List(
+            list, None, None,
+        )))
     }
 }
 
@@ -190,19 +217,28 @@ impl NativeFunction for ConcatFn {
         "concat".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::at_least(0)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         let mut ast = data.destructure().0;
         if ast.len() == 0 {
-            return Ok(FunctionCallResultSuccess::Value(AstNode::List(vec![])));
+            return Ok(FunctionCallResultSuccess::Value(AstNode::List(
+                vector![],
+                None,
+                None,
+            )));
         }
 
         let mut list = ast.remove(0).try_unwrap_list_or_vector()?;
         while ast.len() > 0 {
-            let mut to_append = ast.remove(0).try_unwrap_list_or_vector()?;
-            list.append(&mut to_append);
+            list.append(ast.remove(0).try_unwrap_list_or_vector()?);
         }
 
-        Ok(FunctionCallResultSuccess::Value(AstNode::List(list)))
+        Ok(FunctionCallResultSuccess::Value(AstNode::List(
+            list, None, None,
+        )))
     }
 }
 
@@ -216,11 +252,15 @@ impl NativeFunction for VecFn {
         "vec".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
         let elements = match data.destructure().0.remove(0) {
-            AstNode::List(elements) => elements,
-            AstNode::Vector(elements) => elements,
+            AstNode::List(elements, _, _) => elements,
+            AstNode::Vector(elements, _) => elements,
             x => {
                 return Err(EvalError::TypeError {
                     expected: "List/Vector".to_string(),
@@ -228,6 +268,156 @@ impl NativeFunction for VecFn {
                 })
             }
         };
-        Ok(FunctionCallResultSuccess::Value(AstNode::Vector(elements)))
+        Ok(FunctionCallResultSuccess::Value(AstNode::Vector(
+            elements, None,
+        )))
+    }
+}
+
+// the elements of a collection, as seen by seq and rest.
+// maps give [key value] vectors, strings their characters, sorted collections are in key order.
+fn seq_elements(node: AstNode) -> Result<Vector<AstNode>, EvalError> {
+    Ok(match node {
+        AstNode::List(elements, _, _) => elements,
+        AstNode::Vector(elements, _) => elements,
+        AstNode::String(str) => str
+            .chars()
+            .map(|ch| AstNode::String(ch.to_string()))
+            .collect(),
+        AstNode::HashMap(hm, _) => hm
+            .into_iter()
+            .map(|(key, value)| AstNode::Vector(vector![key.into_ast_node(), value], None))
+            .collect(),
+        AstNode::SortedMap(map, _) => map
+            .into_entries()
+            .into_iter()
+            .map(|(key, value)| AstNode::Vector(vector![key.into_ast_node(), value], None))
+            .collect(),
+        AstNode::Set(set, _) => set.into_iter().map(|x| x.into_ast_node()).collect(),
+        AstNode::SortedSet(set, _) => set
+            .into_entries()
+            .into_iter()
+            .map(|(x, _)| x.into_ast_node())
+            .collect(),
+        AstNode::Nil => Vector::new(),
+        x => {
+            return Err(EvalError::TypeError {
+                expected: "List/Vector/String/Hashmap/Set/Nil".to_string(),
+                got: x,
+            })
+        }
+    })
+}
+
+struct SeqFn;
+impl NativeFunction for SeqFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "seq".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), Some(1))?;
+        let elements = seq_elements(data.destructure().0.remove(0))?;
+
+        // empty collections are turned into nil
+        if elements.is_empty() {
+            Ok(FunctionCallResultSuccess::Value(AstNode::Nil))
+        } else {
+            Ok(FunctionCallResultSuccess::Value(AstNode::List(
+                elements, None, None,
+            )))
+        }
+    }
+}
+
+struct ConjFn;
+impl NativeFunction for ConjFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "conj".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::at_least(1)
+    }
+
+    // lists get the new elements at the front, vectors at the back, sets have no order.
+    // maps get [key value] vectors, like assoc. metadata is kept.
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), None)?;
+        let evaluator = data.evaluator();
+        let (mut ast, env) = data.destructure();
+        let collection = ast.remove(0);
+
+        Ok(FunctionCallResultSuccess::Value(match collection {
+            AstNode::List(mut list, _, meta) => {
+                for element in ast {
+                    list.push_front(element);
+                }
+                AstNode::List(list, None, meta)
+            }
+            AstNode::Vector(mut vector, meta) => {
+                vector.extend(ast);
+                AstNode::Vector(vector, meta)
+            }
+            AstNode::Set(mut set, meta) => {
+                for element in ast {
+                    set.insert(element.try_unwrap_hashmap_key()?);
+                }
+                AstNode::Set(set, meta)
+            }
+            AstNode::SortedSet(mut set, meta) => {
+                let mut call = comparator_call(evaluator, env);
+                for element in ast {
+                    set.insert(element.try_unwrap_hashmap_key()?, (), &mut call)?;
+                }
+                AstNode::SortedSet(set, meta)
+            }
+            AstNode::HashMap(mut map, meta) => {
+                for element in ast {
+                    let (key, value) = map_entry(element)?;
+                    map.insert(key, value);
+                }
+                AstNode::HashMap(map, meta)
+            }
+            AstNode::SortedMap(mut map, meta) => {
+                let mut call = comparator_call(evaluator, env);
+                for element in ast {
+                    let (key, value) = map_entry(element)?;
+                    map.insert(key, value, &mut call)?;
+                }
+                AstNode::SortedMap(map, meta)
+            }
+            x => {
+                return Err(EvalError::TypeError {
+                    expected: "List/Vector/Set/Hashmap".to_string(),
+                    got: x,
+                })
+            }
+        }))
+    }
+}
+
+// a [key value] vector added to a map by conj
+fn map_entry(element: AstNode) -> Result<(HashMapKey, AstNode), EvalError> {
+    match element {
+        AstNode::Vector(entry, _) if entry.len() == 2 => {
+            Ok((entry[0].clone().try_unwrap_hashmap_key()?, entry[1].clone()))
+        }
+        x => Err(EvalError::TypeError {
+            expected: "Vector of a key and a value".to_string(),
+            got: x,
+        }),
     }
 }
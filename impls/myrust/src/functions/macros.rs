@@ -5,7 +5,9 @@ use crate::{
     read::AstNode,
 };
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![Rc::new(DefMacroFn), Rc::new(IsMacroFn)]
@@ -21,6 +23,10 @@ impl NativeFunction for DefMacroFn {
         "defmacro!".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(2))?;
 
@@ -31,7 +37,9 @@ impl NativeFunction for DefMacroFn {
 
         let value = data.evaluator().eval(value, env.clone())?;
         let value = match value {
-            AstNode::Lambda(func) => AstNode::Lambda(Rc::new((*func).clone().set_is_macro(true))),
+            AstNode::Lambda(func, meta) => {
+                AstNode::Lambda(Rc::new((*func).clone().set_is_macro(true)), meta)
+            }
             x => {
                 return Err(EvalError::TypeError {
                     expected: "fn* lambda".to_string(),
@@ -57,6 +65,10 @@ impl NativeFunction for IsMacroFn {
         "macro?".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
@@ -65,7 +77,7 @@ impl NativeFunction for IsMacroFn {
         let function = params.remove(0);
 
         let is_macro = match function {
-            AstNode::Lambda(func) => (*func).is_macro,
+            AstNode::Lambda(func, _) => (*func).is_macro,
             _ => false,
         };
 
@@ -1,8 +1,13 @@
-use std::rc::Rc;
+use std::{cmp::Ordering, rc::Rc};
 
-use crate::read::AstNode;
+use crate::{
+    eval::EvalError,
+    read::{AstNode, BigInt},
+};
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![
@@ -11,6 +16,23 @@ pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
         Rc::new(MathBinaryOp::Times),
         Rc::new(MathBinaryOp::Divide),
         Rc::new(IsNumberFn),
+        Rc::new(IntegerDivisionOp::Quot),
+        Rc::new(IntegerDivisionOp::Rem),
+        Rc::new(IntegerDivisionOp::Mod),
+        Rc::new(AbsFn),
+        Rc::new(MinMaxFn::Min),
+        Rc::new(MinMaxFn::Max),
+        Rc::new(PowFn),
+        Rc::new(IncDecFn::Inc),
+        Rc::new(IncDecFn::Dec),
+        Rc::new(NumberPredicateFn::Zero),
+        Rc::new(NumberPredicateFn::Even),
+        Rc::new(NumberPredicateFn::Odd),
+        Rc::new(BitwiseOp::And),
+        Rc::new(BitwiseOp::Or),
+        Rc::new(BitwiseOp::Xor),
+        Rc::new(BitwiseOp::ShiftLeft),
+        Rc::new(BitwiseOp::ShiftRight),
     ]
 }
 
@@ -36,19 +58,136 @@ impl NativeFunction for MathBinaryOp {
         .to_string()
     }
 
+    fn arity(&self) -> Arity {
+        match self {
+            Self::Plus | Self::Times => Arity::at_least(0),
+            Self::Minus | Self::Divide => Arity::at_least(1),
+        }
+    }
+
+    // folds over all the arguments. (- x) is a negation and (/ x) a reciprocal,
+    // (+) and (*) return the identity.
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
-        data.check_parameters_count_range(Some(2), Some(2))?;
+        if matches!(self, Self::Minus | Self::Divide) {
+            data.check_parameters_count_range(Some(1), None)?;
+        }
         let (mut ast, _env) = data.destructure();
-        let a = ast.remove(0).try_unwrap_int()?;
-        let b = ast.remove(0).try_unwrap_int()?;
+
+        let mut result = if ast.len() > 1 {
+            ast.remove(0)
+        } else {
+            self.identity()
+        };
+        for operand in ast {
+            result = self.apply(result, operand)?;
+        }
+
+        Ok(FunctionCallResultSuccess::Value(result))
+    }
+}
+
+impl MathBinaryOp {
+    fn identity(&self) -> AstNode {
+        match self {
+            Self::Plus | Self::Minus => AstNode::Int(0),
+            Self::Times | Self::Divide => AstNode::Int(1),
+        }
+    }
+
+    fn apply(&self, a: AstNode, b: AstNode) -> Result<AstNode, EvalError> {
+        let result = match NumberPair::promote(a, b)? {
+            NumberPair::Ints(_, 0) if matches!(self, Self::Divide) => {
+                return Err(EvalError::ArithmeticError("Division by zero".to_string()))
+            }
+            NumberPair::Ints(a, b) => {
+                let result = match self {
+                    Self::Plus => a.checked_add(b),
+                    Self::Minus => a.checked_sub(b),
+                    Self::Times => a.checked_mul(b),
+                    Self::Divide => a.checked_div(b),
+                };
+                match result {
+                    Some(result) => AstNode::Int(result),
+                    // overflow: retry with arbitrary precision
+                    None => self.run_bigint(BigInt::from(a), BigInt::from(b))?,
+                }
+            }
+            NumberPair::BigInts(a, b) => self.run_bigint(a, b)?,
+            NumberPair::Floats(a, b) => AstNode::Float(match self {
+                Self::Plus => a + b,
+                Self::Minus => a - b,
+                Self::Times => a * b,
+                Self::Divide => a / b,
+            }),
+        };
+
+        Ok(result)
+    }
+
+    fn run_bigint(&self, a: BigInt, b: BigInt) -> Result<AstNode, EvalError> {
         let result = match self {
-            Self::Plus => a + b,
-            Self::Minus => a - b,
-            Self::Times => a * b,
-            Self::Divide => a / b,
+            Self::Plus => &a + &b,
+            Self::Minus => &a - &b,
+            Self::Times => &a * &b,
+            Self::Divide => match a.div_rem(&b) {
+                Some((quotient, _)) => quotient,
+                None => return Err(EvalError::ArithmeticError("Division by zero".to_string())),
+            },
         };
+        Ok(AstNode::from_bigint(result))
+    }
+}
 
-        Ok(FunctionCallResultSuccess::Value(AstNode::Int(result)))
+// numeric ordering with int/float promotion. None when comparing with NaN.
+pub(super) fn compare_numbers(a: AstNode, b: AstNode) -> Result<Option<Ordering>, EvalError> {
+    Ok(match NumberPair::promote(a, b)? {
+        NumberPair::Ints(a, b) => a.partial_cmp(&b),
+        NumberPair::BigInts(a, b) => a.partial_cmp(&b),
+        NumberPair::Floats(a, b) => a.partial_cmp(&b),
+    })
+}
+
+// two numeric operands, converted to a common type: Int, then BigInt, then Float.
+enum NumberPair {
+    Ints(i64, i64),
+    BigInts(BigInt, BigInt),
+    Floats(f64, f64),
+}
+
+impl NumberPair {
+    fn promote(a: AstNode, b: AstNode) -> Result<NumberPair, EvalError> {
+        match (a, b) {
+            (AstNode::Int(a), AstNode::Int(b)) => Ok(NumberPair::Ints(a, b)),
+            (AstNode::Float(a), b) => Ok(NumberPair::Floats(a, Self::to_float(b)?)),
+            (a, AstNode::Float(b)) => Ok(NumberPair::Floats(Self::to_float(a)?, b)),
+            (a, b) => Ok(NumberPair::BigInts(
+                Self::to_bigint(a)?,
+                Self::to_bigint(b)?,
+            )),
+        }
+    }
+
+    fn to_float(node: AstNode) -> Result<f64, EvalError> {
+        match node {
+            AstNode::Int(num) => Ok(num as f64),
+            AstNode::BigInt(num) => Ok(num.to_f64()),
+            AstNode::Float(num) => Ok(num),
+            got => Err(EvalError::TypeError {
+                expected: "Number".to_string(),
+                got,
+            }),
+        }
+    }
+
+    fn to_bigint(node: AstNode) -> Result<BigInt, EvalError> {
+        match node {
+            AstNode::Int(num) => Ok(BigInt::from(num)),
+            AstNode::BigInt(num) => Ok(num),
+            got => Err(EvalError::TypeError {
+                expected: "Number".to_string(),
+                got,
+            }),
+        }
     }
 }
 
@@ -62,12 +201,391 @@ impl NativeFunction for IsNumberFn {
         "number?".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
         let node = data.destructure().0.remove(0);
-        let is_number = node.try_unwrap_int().is_ok();
+        let is_number = matches!(
+            node,
+            AstNode::Int(_) | AstNode::BigInt(_) | AstNode::Float(_)
+        );
 
         return Ok(FunctionCallResultSuccess::Value(AstNode::Bool(is_number)));
     }
 }
+
+// quot truncates towards zero, rem has the sign of the dividend and mod the sign of the divisor
+enum IntegerDivisionOp {
+    Quot,
+    Rem,
+    Mod,
+}
+impl NativeFunction for IntegerDivisionOp {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Quot => "quot",
+            Self::Rem => "rem",
+            Self::Mod => "mod",
+        }
+        .to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(2), Some(2))?;
+        let (mut ast, _env) = data.destructure();
+
+        let result = match NumberPair::promote(ast.remove(0), ast.remove(0))? {
+            NumberPair::Ints(_, 0) => return Err(self.zero_divisor_error()),
+            NumberPair::Ints(a, b) => {
+                let result = match self {
+                    Self::Quot => a.checked_div(b),
+                    Self::Rem | Self::Mod => a.checked_rem(b),
+                };
+                match result {
+                    Some(result)
+                        if matches!(self, Self::Mod) && result != 0 && (result < 0) != (b < 0) =>
+                    {
+                        AstNode::Int(result + b)
+                    }
+                    Some(result) => AstNode::Int(result),
+                    // overflow: retry with arbitrary precision
+                    None => self.run_bigint(BigInt::from(a), BigInt::from(b))?,
+                }
+            }
+            NumberPair::BigInts(a, b) => self.run_bigint(a, b)?,
+            NumberPair::Floats(_, 0.0) => return Err(self.zero_divisor_error()),
+            NumberPair::Floats(a, b) => AstNode::Float(match self {
+                Self::Quot => (a / b).trunc(),
+                Self::Rem => a % b,
+                Self::Mod if a % b != 0.0 && (a % b < 0.0) != (b < 0.0) => a % b + b,
+                Self::Mod => a % b,
+            }),
+        };
+
+        Ok(FunctionCallResultSuccess::Value(result))
+    }
+}
+
+impl IntegerDivisionOp {
+    fn zero_divisor_error(&self) -> EvalError {
+        match self {
+            Self::Quot => EvalError::ArithmeticError("Division by zero".to_string()),
+            Self::Rem | Self::Mod => EvalError::ArithmeticError("Invalid modulus: 0".to_string()),
+        }
+    }
+
+    fn run_bigint(&self, a: BigInt, b: BigInt) -> Result<AstNode, EvalError> {
+        let (quotient, remainder) = a.div_rem(&b).ok_or_else(|| self.zero_divisor_error())?;
+        let result = match self {
+            Self::Quot => quotient,
+            Self::Mod if !remainder.is_zero() && remainder.is_negative() != b.is_negative() => {
+                &remainder + &b
+            }
+            Self::Rem | Self::Mod => remainder,
+        };
+        Ok(AstNode::from_bigint(result))
+    }
+}
+
+struct AbsFn;
+impl NativeFunction for AbsFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "abs".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), Some(1))?;
+
+        let result = match data.destructure().0.remove(0) {
+            AstNode::Int(num) => match num.checked_abs() {
+                Some(num) => AstNode::Int(num),
+                None => AstNode::from_bigint(-&BigInt::from(num)),
+            },
+            AstNode::BigInt(num) if num.is_negative() => AstNode::from_bigint(-&num),
+            AstNode::BigInt(num) => AstNode::BigInt(num),
+            AstNode::Float(num) => AstNode::Float(num.abs()),
+            got => {
+                return Err(EvalError::TypeError {
+                    expected: "Number".to_string(),
+                    got,
+                })
+            }
+        };
+
+        Ok(FunctionCallResultSuccess::Value(result))
+    }
+}
+
+enum MinMaxFn {
+    Min,
+    Max,
+}
+impl NativeFunction for MinMaxFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Min => "min",
+            Self::Max => "max",
+        }
+        .to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::at_least(1)
+    }
+
+    // returns the argument itself, without promotion: (max 1 2.0) is 2.0
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), None)?;
+
+        let (params, _env) = data.destructure();
+        let mut params = params.into_iter();
+        let mut result = params.next().unwrap();
+        for candidate in params {
+            let replace = match compare_numbers(candidate.clone(), result.clone())? {
+                Some(Ordering::Less) => matches!(self, Self::Min),
+                Some(Ordering::Greater) => matches!(self, Self::Max),
+                _ => false,
+            };
+            if replace {
+                result = candidate;
+            }
+        }
+
+        Ok(FunctionCallResultSuccess::Value(result))
+    }
+}
+
+// exact for integers with a non-negative integer exponent, a float otherwise.
+// a single call can't be interrupted, so exact results are limited to MAX_POW_BITS.
+struct PowFn;
+
+const MAX_POW_BITS: u64 = 1 << 18;
+
+impl PowFn {
+    fn exact(base: &BigInt, exponent: i64) -> Result<AstNode, EvalError> {
+        // the result has at least (bits - 1) * exponent + 1 bits, 0 and 1 stay small
+        let min_bits = base
+            .bits()
+            .saturating_sub(1)
+            .saturating_mul(exponent as u64);
+        if min_bits > MAX_POW_BITS {
+            return Err(EvalError::ArithmeticError(format!(
+                "pow: the result would have more than {} bits",
+                MAX_POW_BITS
+            )));
+        }
+        Ok(AstNode::from_bigint(base.pow(exponent as u64)))
+    }
+}
+
+impl NativeFunction for PowFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "pow".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(2), Some(2))?;
+        let (mut ast, _env) = data.destructure();
+
+        let result = match (ast.remove(0), ast.remove(0)) {
+            (AstNode::Int(base), AstNode::Int(exponent)) if exponent >= 0 => {
+                match u32::try_from(exponent)
+                    .ok()
+                    .and_then(|e| base.checked_pow(e))
+                {
+                    Some(result) => AstNode::Int(result),
+                    None => Self::exact(&BigInt::from(base), exponent)?,
+                }
+            }
+            (AstNode::BigInt(base), AstNode::Int(exponent)) if exponent >= 0 => {
+                Self::exact(&base, exponent)?
+            }
+            (base, exponent) => {
+                AstNode::Float(NumberPair::to_float(base)?.powf(NumberPair::to_float(exponent)?))
+            }
+        };
+
+        Ok(FunctionCallResultSuccess::Value(result))
+    }
+}
+
+enum IncDecFn {
+    Inc,
+    Dec,
+}
+impl NativeFunction for IncDecFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Inc => "inc",
+            Self::Dec => "dec",
+        }
+        .to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), Some(1))?;
+
+        let num = data.destructure().0.remove(0);
+        let result = match self {
+            Self::Inc => MathBinaryOp::Plus.apply(num, AstNode::Int(1))?,
+            Self::Dec => MathBinaryOp::Minus.apply(num, AstNode::Int(1))?,
+        };
+
+        Ok(FunctionCallResultSuccess::Value(result))
+    }
+}
+
+enum NumberPredicateFn {
+    Zero,
+    Even,
+    Odd,
+}
+impl NativeFunction for NumberPredicateFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Zero => "zero?",
+            Self::Even => "even?",
+            Self::Odd => "odd?",
+        }
+        .to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), Some(1))?;
+
+        let result = match (self, data.destructure().0.remove(0)) {
+            (Self::Zero, AstNode::Int(num)) => num == 0,
+            (Self::Zero, AstNode::BigInt(num)) => num.is_zero(),
+            (Self::Zero, AstNode::Float(num)) => num == 0.0,
+            (Self::Even, AstNode::Int(num)) => num % 2 == 0,
+            (Self::Even, AstNode::BigInt(num)) => !num.is_odd(),
+            (Self::Odd, AstNode::Int(num)) => num % 2 != 0,
+            (Self::Odd, AstNode::BigInt(num)) => num.is_odd(),
+            (Self::Zero, got) => {
+                return Err(EvalError::TypeError {
+                    expected: "Number".to_string(),
+                    got,
+                })
+            }
+            (_, got) => {
+                return Err(EvalError::TypeError {
+                    expected: "Integer".to_string(),
+                    got,
+                })
+            }
+        };
+
+        Ok(FunctionCallResultSuccess::Value(AstNode::Bool(result)))
+    }
+}
+
+// bitwise operations on 64 bit integers: bits shifted out are lost, like in clojure
+enum BitwiseOp {
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+}
+impl NativeFunction for BitwiseOp {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::And => "bit-and",
+            Self::Or => "bit-or",
+            Self::Xor => "bit-xor",
+            Self::ShiftLeft => "bit-shift-left",
+            Self::ShiftRight => "bit-shift-right",
+        }
+        .to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        match self {
+            Self::And | Self::Or | Self::Xor => Arity::at_least(2),
+            Self::ShiftLeft | Self::ShiftRight => Arity::exactly(2),
+        }
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        match self {
+            Self::And | Self::Or | Self::Xor => data.check_parameters_count_range(Some(2), None)?,
+            Self::ShiftLeft | Self::ShiftRight => {
+                data.check_parameters_count_range(Some(2), Some(2))?
+            }
+        }
+        let (ast, _env) = data.destructure();
+
+        let mut ast = ast.into_iter();
+        let mut result = ast.next().unwrap().try_unwrap_int()?;
+        for operand in ast {
+            let operand = operand.try_unwrap_int()?;
+            result = match self {
+                Self::And => result & operand,
+                Self::Or => result | operand,
+                Self::Xor => result ^ operand,
+                Self::ShiftLeft | Self::ShiftRight if !(0..64).contains(&operand) => {
+                    return Err(EvalError::ArithmeticError(format!(
+                        "Invalid shift amount: {}",
+                        operand
+                    )))
+                }
+                Self::ShiftLeft => result << operand,
+                Self::ShiftRight => result >> operand,
+            };
+        }
+
+        Ok(FunctionCallResultSuccess::Value(AstNode::Int(result)))
+    }
+}
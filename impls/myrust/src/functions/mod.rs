@@ -15,6 +15,8 @@ mod macros;
 mod math;
 mod printing;
 mod quote;
+mod sets;
+mod sorted;
 mod symbol;
 mod var_declarations;
 
@@ -40,6 +42,8 @@ pub fn global_functions() -> Vec<Rc<dyn NativeFunction>> {
     fns.append(&mut exceptions::functions());
     fns.append(&mut symbol::functions());
     fns.append(&mut hashmaps::functions());
+    fns.append(&mut sets::functions());
+    fns.append(&mut sorted::functions());
     fns.append(&mut io::functions());
 
     return fns;
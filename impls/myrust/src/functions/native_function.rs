@@ -1,18 +1,30 @@
+use std::rc::Rc;
+
 use crate::read::AstNode;
 
-use crate::eval::{EvalError, Evaluator, SharedEnvironment};
+use crate::eval::{EvalError, Evaluator, Form, SharedEnvironment};
 
 pub struct TailCallData {
-    to_eval: AstNode,
+    to_eval: Form<'static>,
     env: SharedEnvironment,
 }
 
 impl TailCallData {
-    pub fn destructure(self) -> (AstNode, SharedEnvironment) {
+    pub fn destructure(self) -> (Form<'static>, SharedEnvironment) {
         (self.to_eval, self.env)
     }
     pub fn new(to_eval: AstNode, env: SharedEnvironment) -> TailCallData {
-        Self { to_eval, env }
+        Self {
+            to_eval: Form::Owned(to_eval),
+            env,
+        }
+    }
+    // used for lambda bodies, which are evaluated without copying them
+    pub fn shared(to_eval: Rc<AstNode>, env: SharedEnvironment) -> TailCallData {
+        Self {
+            to_eval: Form::Shared(to_eval),
+            env,
+        }
     }
 }
 
@@ -70,25 +82,61 @@ impl FunctionCallData {
     }
 }
 
+// how many arguments a function takes, shown in the repl hints
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn exactly(count: usize) -> Self {
+        Self {
+            min: count,
+            max: Some(count),
+        }
+    }
+    pub fn at_least(min: usize) -> Self {
+        Self { min, max: None }
+    }
+    pub fn between(min: usize, max: usize) -> Self {
+        Self {
+            min,
+            max: Some(max),
+        }
+    }
+}
+
 pub trait NativeFunction {
     fn evaluates_arguments(&self) -> bool;
     fn name(&self) -> String;
+    fn arity(&self) -> Arity;
     fn run(&self, data: FunctionCallData) -> FunctionCallResult;
 }
 
 impl dyn NativeFunction {
-    pub fn eval_params_and_run(
+    pub fn eval_params_and_run<'a>(
         &self,
-        mut params: Vec<AstNode>,
+        params: impl Iterator<Item = &'a AstNode>,
         env: SharedEnvironment,
         evaluator: Evaluator,
     ) -> FunctionCallResult {
-        if self.evaluates_arguments() {
+        let params = if self.evaluates_arguments() {
             // evaluate the parameters in advance only if it's not a special form (or macro?)
-            let r = params.into_iter().map(|t| evaluator.eval(t, env.clone()));
-            params = Result::from_iter(r)?
-        }
+            let r = params.map(|t| evaluator.eval_borrowed(t, env.clone()));
+            Result::from_iter(r)?
+        } else {
+            params.cloned().collect()
+        };
+        self.run_with_params(params, env, evaluator)
+    }
 
+    pub fn run_with_params(
+        &self,
+        params: Vec<AstNode>,
+        env: SharedEnvironment,
+        evaluator: Evaluator,
+    ) -> FunctionCallResult {
         evaluator.trace_native_funcall(self, &params);
         self.run(FunctionCallData {
             call_context: Some((params, env)),
@@ -2,7 +2,9 @@ use std::rc::Rc;
 
 use crate::read::{AstNode, AstPrintFormat, AstPrinter};
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![
@@ -68,6 +70,10 @@ impl NativeFunction for PrintFunction {
         self.name.to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::at_least(0)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         let mut builder = string_builder::Builder::new(64);
         let mut first_print = true;
@@ -108,6 +114,10 @@ impl NativeFunction for IsStringFn {
         "string?".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
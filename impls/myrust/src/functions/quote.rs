@@ -1,11 +1,15 @@
-use std::{collections::HashMap, rc::Rc};
+use std::rc::Rc;
+
+use im_rc::{vector, HashMap, Vector};
 
 use crate::{
     eval::{EvalError, Evaluator, SharedEnvironment},
     read::AstNode,
 };
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![Rc::new(QuoteFn), Rc::new(QuasiQuoteFn)]
@@ -21,6 +25,10 @@ impl NativeFunction for QuoteFn {
         "quote".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
         Ok(FunctionCallResultSuccess::Value(
@@ -38,12 +46,16 @@ impl NativeFunction for QuasiQuoteFn {
         "quasiquote".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
         let evaluator = data.evaluator();
         let (ast, env) = data.destructure();
 
-        let mut ast = process_quasiquote(ast, &env, &evaluator)?;
+        let mut ast = process_quasiquote(ast.into(), &env, &evaluator)?;
         if ast.len() != 1 {
             return Err(EvalError::custom_exception_str(
                 "quasiquote cannot return more than 1 argument",
@@ -55,20 +67,20 @@ impl NativeFunction for QuasiQuoteFn {
 }
 
 fn process_quasiquote(
-    mut input_ast: Vec<AstNode>,
+    input_ast: Vector<AstNode>,
     env: &SharedEnvironment,
     evaluator: &Evaluator,
-) -> Result<Vec<AstNode>, EvalError> {
-    let mut result = vec![];
-    while input_ast.len() > 0 {
-        let mut expansion = match input_ast.remove(0) {
-            AstNode::List(args) if args.len() > 0 => match &args[0] {
-                AstNode::UnresolvedSymbol(name) if name == "unquote" => {
-                    vec![quasiquote_run_unquote(args, env, evaluator)?]
+) -> Result<Vector<AstNode>, EvalError> {
+    let mut result = Vector::new();
+    for node in input_ast {
+        let expansion = match node {
+            AstNode::List(args, span, _) if args.len() > 0 => match &args[0] {
+                AstNode::UnresolvedSymbol(name, _) if name.name() == "unquote" => {
+                    vector![quasiquote_run_unquote(args, env, evaluator)?]
                 }
-                AstNode::UnresolvedSymbol(name) if name == "splice-unquote" => {
+                AstNode::UnresolvedSymbol(name, _) if name.name() == "splice-unquote" => {
                     let result = quasiquote_run_unquote(args, env, evaluator)?;
-                    if let AstNode::List(result) = result {
+                    if let AstNode::List(result, _, _) = result {
                         result
                     } else {
                         Err(EvalError::custom_exception_str(
@@ -76,15 +88,20 @@ fn process_quasiquote(
                         ))?
                     }
                 }
-                _ => vec![AstNode::List(process_quasiquote(args, env, evaluator)?)],
+                _ => vector![AstNode::List(
+                    process_quasiquote(args, env, evaluator)?,
+                    span,
+                    None,
+                )],
             },
-            AstNode::Vector(elements) => vec![AstNode::Vector(process_quasiquote(
-                elements, env, evaluator,
-            )?)],
-            AstNode::HashMap(hm) => {
+            AstNode::Vector(elements, meta) => vector![AstNode::Vector(
+                process_quasiquote(elements, env, evaluator)?,
+                meta,
+            )],
+            AstNode::HashMap(hm, meta) => {
                 let mut result = HashMap::new();
                 for entry in hm {
-                    let mut value = process_quasiquote(vec![entry.1], env, evaluator)?;
+                    let mut value = process_quasiquote(vector![entry.1], env, evaluator)?;
                     if value.len() != 1 {
                         Err(EvalError::custom_exception_str(
                             "Hashmap value has a splice-unquote",
@@ -92,17 +109,26 @@ fn process_quasiquote(
                     }
                     result.insert(entry.0, value.remove(0));
                 }
-                vec![AstNode::HashMap(result)]
+                vector![AstNode::HashMap(result, meta)]
+            }
+            AstNode::Set(set, meta) => {
+                // splice-unquote is allowed here: every spliced element becomes an element of the set
+                let elements = set.into_iter().map(|x| x.into_ast_node()).collect();
+                let elements = process_quasiquote(elements, env, evaluator)?;
+                vector![AstNode::Set(
+                    Result::from_iter(elements.into_iter().map(|x| x.try_unwrap_hashmap_key()))?,
+                    meta,
+                )]
             }
-            x => vec![x], // all other nodes are left as-is, as they are not inside an 'unquote' and they don't contain other ASTs
+            x => vector![x], // all other nodes are left as-is, as they are not inside an 'unquote' and they don't contain other ASTs
         };
-        result.append(&mut expansion);
+        result.append(expansion);
     }
     return Ok(result);
 }
 
 fn quasiquote_run_unquote(
-    mut args: Vec<AstNode>,
+    mut args: Vector<AstNode>,
     env: &SharedEnvironment,
     evaluator: &Evaluator,
 ) -> Result<AstNode, EvalError> {
@@ -0,0 +1,184 @@
+use std::rc::Rc;
+
+use im_rc::{HashSet, Vector};
+
+use crate::{
+    eval::EvalError,
+    read::{AstNode, HashMapKey},
+};
+
+use super::sorted::comparator_call;
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
+
+pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
+    vec![
+        Rc::new(SetFn),
+        Rc::new(IsSetFn),
+        Rc::new(DisjFn),
+        Rc::new(SetOperationFn::Union),
+        Rc::new(SetOperationFn::Intersection),
+        Rc::new(SetOperationFn::Difference),
+    ]
+}
+
+struct SetFn;
+impl NativeFunction for SetFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "set".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), Some(1))?;
+
+        let elements = match data.destructure().0.remove(0) {
+            AstNode::List(elements, _, _) => elements,
+            AstNode::Vector(elements, _) => elements,
+            AstNode::Set(set, _) => {
+                return Ok(FunctionCallResultSuccess::Value(AstNode::Set(set, None)))
+            }
+            AstNode::SortedSet(set, _) => set
+                .into_entries()
+                .into_iter()
+                .map(|x| x.0.into_ast_node())
+                .collect(),
+            AstNode::Nil => Vector::new(),
+            x => {
+                return Err(EvalError::TypeError {
+                    expected: "List/Vector/Set/Nil".to_string(),
+                    got: x,
+                })
+            }
+        };
+        let set = Result::from_iter(
+            elements
+                .into_iter()
+                .map(|element| element.try_unwrap_hashmap_key()),
+        )?;
+        Ok(FunctionCallResultSuccess::Value(AstNode::Set(set, None)))
+    }
+}
+
+struct IsSetFn;
+impl NativeFunction for IsSetFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "set?".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), Some(1))?;
+
+        Ok(FunctionCallResultSuccess::Value(AstNode::Bool(matches!(
+            data.destructure().0.remove(0),
+            AstNode::Set(_, _) | AstNode::SortedSet(_, _)
+        ))))
+    }
+}
+
+struct DisjFn;
+impl NativeFunction for DisjFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "disj".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::at_least(1)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), None)?;
+
+        let evaluator = data.evaluator();
+        let (mut ast, env) = data.destructure();
+
+        let set = ast.remove(0);
+        if set == AstNode::Nil {
+            // same as dissoc
+            return Ok(FunctionCallResultSuccess::Value(AstNode::Nil));
+        }
+        if let AstNode::SortedSet(mut set, meta) = set {
+            let mut call = comparator_call(evaluator, env);
+            for element in ast {
+                set.remove(&element.try_unwrap_hashmap_key()?, &mut call)?;
+            }
+            return Ok(FunctionCallResultSuccess::Value(AstNode::SortedSet(
+                set, meta,
+            )));
+        }
+        let meta = set.meta();
+        let mut set = set.try_unwrap_set()?;
+
+        for element in ast {
+            set.remove(&element.try_unwrap_hashmap_key()?) /* ignore missing values */;
+        }
+
+        Ok(FunctionCallResultSuccess::Value(AstNode::Set(set, meta)))
+    }
+}
+
+enum SetOperationFn {
+    Union,
+    Intersection,
+    Difference,
+}
+impl SetOperationFn {
+    fn apply(&self, a: HashSet<HashMapKey>, b: &HashSet<HashMapKey>) -> HashSet<HashMapKey> {
+        match self {
+            Self::Union => a.union(b.clone()),
+            Self::Intersection => a.into_iter().filter(|x| b.contains(x)).collect(),
+            Self::Difference => a.into_iter().filter(|x| !b.contains(x)).collect(),
+        }
+    }
+}
+impl NativeFunction for SetOperationFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Union => "union",
+            Self::Intersection => "intersection",
+            Self::Difference => "difference",
+        }
+        .to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::at_least(1)
+    }
+
+    // folds over all the sets from left to right, like clojure.set
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), None)?;
+
+        let mut ast = data.destructure().0;
+        let mut result = ast.remove(0).try_unwrap_set()?;
+        for set in ast {
+            result = self.apply(result, &set.try_unwrap_set()?);
+        }
+
+        Ok(FunctionCallResultSuccess::Value(AstNode::Set(result, None)))
+    }
+}
@@ -0,0 +1,166 @@
+use std::{cmp::Ordering, rc::Rc};
+
+use im_rc::vector;
+
+use crate::{
+    eval::{EvalError, Evaluator, SharedEnvironment},
+    read::{AstNode, Sorted},
+};
+
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
+
+pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
+    vec![
+        Rc::new(SortedMapFn::Natural),
+        Rc::new(SortedMapFn::By),
+        Rc::new(SortedSetFn),
+        Rc::new(IsSortedFn),
+    ]
+}
+
+// calls the comparator of a sorted-map-by on two keys.
+// like in clojure, the comparator can return a number, or be a predicate like <
+pub(super) fn comparator_call(
+    evaluator: Evaluator,
+    env: SharedEnvironment,
+) -> impl FnMut(&AstNode, &AstNode, &AstNode) -> Result<Ordering, EvalError> {
+    move |comparator, a, b| {
+        let call = |a: &AstNode, b: &AstNode| {
+            let quote = |x: &AstNode| {
+                AstNode::List(
+                    vector![AstNode::create_symbol("quote"), x.clone()],
+                    None,
+                    None,
+                )
+            };
+            evaluator.eval(
+                AstNode::List(vector![comparator.clone(), quote(a), quote(b)], None, None),
+                env.clone(),
+            )
+        };
+
+        match call(a, b)? {
+            AstNode::Int(num) => Ok(num.cmp(&0)),
+            AstNode::Bool(true) => Ok(Ordering::Less),
+            AstNode::Bool(false) | AstNode::Nil => match call(b, a)? {
+                AstNode::Bool(true) => Ok(Ordering::Greater),
+                _ => Ok(Ordering::Equal),
+            },
+            x => Err(EvalError::TypeError {
+                expected: "Int or Bool from the comparator".to_string(),
+                got: x,
+            }),
+        }
+    }
+}
+
+enum SortedMapFn {
+    Natural,
+    By,
+}
+impl NativeFunction for SortedMapFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Natural => "sorted-map",
+            Self::By => "sorted-map-by",
+        }
+        .to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        match self {
+            Self::Natural => Arity::at_least(0),
+            Self::By => Arity::at_least(1),
+        }
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        let evaluator = data.evaluator();
+        let (mut ast, env) = data.destructure();
+
+        let mut map = match self {
+            Self::Natural => Sorted::natural(),
+            Self::By if !ast.is_empty() => Sorted::by(ast.remove(0)),
+            Self::By => {
+                return Err(EvalError::ParameterCountError {
+                    expected_min: Some(1),
+                    expected_max: None,
+                    provided: 0,
+                })
+            }
+        };
+        if ast.len() % 2 != 0 {
+            return Err(EvalError::custom_exception_str(
+                "Expected an even number of parameters for sorted-map",
+            ));
+        }
+
+        let mut call = comparator_call(evaluator, env);
+        let mut ast = ast.into_iter();
+        while let (Some(key), Some(value)) = (ast.next(), ast.next()) {
+            map.insert(key.try_unwrap_hashmap_key()?, value, &mut call)?;
+        }
+        Ok(FunctionCallResultSuccess::Value(AstNode::SortedMap(
+            map, None,
+        )))
+    }
+}
+
+struct SortedSetFn;
+impl NativeFunction for SortedSetFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "sorted-set".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::at_least(0)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        let evaluator = data.evaluator();
+        let (ast, env) = data.destructure();
+
+        let mut set = Sorted::natural();
+        let mut call = comparator_call(evaluator, env);
+        for element in ast {
+            set.insert(element.try_unwrap_hashmap_key()?, (), &mut call)?;
+        }
+        Ok(FunctionCallResultSuccess::Value(AstNode::SortedSet(
+            set, None,
+        )))
+    }
+}
+
+struct IsSortedFn;
+impl NativeFunction for IsSortedFn {
+    fn evaluates_arguments(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> String {
+        "sorted?".to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
+    fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
+        data.check_parameters_count_range(Some(1), Some(1))?;
+
+        Ok(FunctionCallResultSuccess::Value(AstNode::Bool(matches!(
+            data.destructure().0.remove(0),
+            AstNode::SortedMap(_, _) | AstNode::SortedSet(_, _)
+        ))))
+    }
+}
@@ -2,7 +2,9 @@ use std::rc::Rc;
 
 use crate::read::AstNode;
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![
@@ -23,11 +25,15 @@ impl NativeFunction for SymbolFn {
         "symbol".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
-        Ok(FunctionCallResultSuccess::Value(AstNode::UnresolvedSymbol(
-            data.destructure().0.remove(0).try_unwrap_string()?,
+        Ok(FunctionCallResultSuccess::Value(AstNode::create_symbol(
+            &data.destructure().0.remove(0).try_unwrap_string()?,
         )))
     }
 }
@@ -42,6 +48,10 @@ impl NativeFunction for IsSymbolFn {
         "symbol?".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
@@ -61,12 +71,20 @@ impl NativeFunction for KeywordFn {
         "keyword".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
-        Ok(FunctionCallResultSuccess::Value(AstNode::create_keyword(
-            &data.destructure().0.remove(0).try_unwrap_string()?,
-        )))
+        // keywords are returned as they are
+        Ok(FunctionCallResultSuccess::Value(
+            match data.destructure().0.remove(0) {
+                AstNode::Keyword(name) => AstNode::Keyword(name),
+                x => AstNode::create_keyword(&x.try_unwrap_string()?),
+            },
+        ))
     }
 }
 
@@ -80,6 +98,10 @@ impl NativeFunction for IsKeywordFn {
         "keyword?".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(1)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(1), Some(1))?;
 
@@ -5,7 +5,9 @@ use crate::{
     read::AstNode,
 };
 
-use super::{FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction};
+use super::{
+    Arity, FunctionCallData, FunctionCallResult, FunctionCallResultSuccess, NativeFunction,
+};
 
 pub fn functions() -> Vec<Rc<dyn NativeFunction>> {
     vec![Rc::new(DefBang), Rc::new(LetStar), Rc::new(GetDefs)]
@@ -21,6 +23,10 @@ impl NativeFunction for DefBang {
         "def!".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(2))?;
 
@@ -47,6 +53,10 @@ impl NativeFunction for LetStar {
         "let*".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(2)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(Some(2), Some(2))?;
         let (mut params, env) = data.destructure();
@@ -88,6 +98,10 @@ impl NativeFunction for GetDefs {
         "getdefs".to_string()
     }
 
+    fn arity(&self) -> Arity {
+        Arity::exactly(0)
+    }
+
     fn run(&self, mut data: FunctionCallData) -> FunctionCallResult {
         data.check_parameters_count_range(None, Some(0))?;
 
@@ -98,7 +112,7 @@ impl NativeFunction for GetDefs {
             .into_iter()
             .map(|string| AstNode::String(string))
             .collect();
-        let value = AstNode::List(keys);
+        let value = AstNode::List(keys, None, None);
         Ok(FunctionCallResultSuccess::Value(value))
     }
 }
@@ -2,19 +2,145 @@ mod environment;
 mod eval;
 mod functions;
 mod read;
-use eval::{new_base_environment, EvalError, Evaluator, SharedEnvironment};
+mod repl;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use eval::{
+    new_base_environment, resolve, Backend, EvalError, Evaluator, SharedEnvironment,
+    DEFAULT_MAX_DEPTH,
+};
 use read::{
-    AstPrintFormat, AstPrinter, InputReader, InputSource, Lexer, Parser, ParsingError,
-    REPLTerminalInputSource, StringInputSource,
+    default_history_file, AstPrinter, InputReader, Lexer, Parser, ParsingError, StringInputSource,
 };
 
+// the stack used by a nested evaluation, with room to spare in the unoptimized build
+const STACK_PER_DEPTH: usize = 32 * 1024;
+
 fn main() {
+    // --max-depth=N limits the nesting of evaluations, deep recursion raises a StackOverflow
+    // error instead of crashing. the evaluation runs in a thread with a stack big enough for it.
+    let mut args: Vec<String> = std::env::args().collect();
+    let mut max_depth = DEFAULT_MAX_DEPTH;
+    if let Some(value) = take_flag(&mut args, "--max-depth") {
+        max_depth = match value.parse() {
+            Ok(max_depth) => max_depth,
+            Err(_) => {
+                println!("Invalid --max-depth, expected a number");
+                return;
+            }
+        };
+    }
+    let evaluation = std::thread::Builder::new()
+        .name("evaluator".to_string())
+        .stack_size((max_depth + 1) * STACK_PER_DEPTH)
+        .spawn(move || run_main(args, max_depth));
+    match evaluation {
+        Ok(evaluation) => {
+            if let Err(panic) = evaluation.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+        Err(err) => println!(
+            "Cannot allocate the stack for --max-depth={}: {}",
+            max_depth, err
+        ),
+    }
+}
+
+fn run_main(mut args: Vec<String>, max_depth: usize) {
     let evaluator = Evaluator::new();
+    evaluator.set_max_depth(max_depth);
     let environment = new_base_environment();
-    let ast_printer = AstPrinter::new(AstPrintFormat::Repr);
-    let mut ast_printer = Some(&ast_printer);
 
-    // load initial environment via file parsing
+    // --backend=tree (the default), --backend=closures or --backend=bytecode, before the file to run
+    if let Some(value) = take_flag(&mut args, "--backend") {
+        let backend = match value.as_str() {
+            "tree" => Backend::TreeWalker,
+            "closures" => Backend::Closures,
+            "bytecode" => Backend::Bytecode,
+            other => {
+                println!(
+                    "Unknown backend '{}', expected 'tree', 'closures' or 'bytecode'",
+                    other
+                );
+                return;
+            }
+        };
+        evaluator.set_backend(backend);
+    }
+
+    // --max-steps=N and --timeout=SECONDS bound the evaluation of untrusted code, starting after
+    // the startup file. they apply to the whole file, or to each entry of the repl.
+    // a step is a form evaluated, on every backend. the tree walker also counts the evaluation
+    // of macros at each call, the compiled backends only when they expand them once.
+    let max_steps = match take_flag(&mut args, "--max-steps").map(|value| value.parse()) {
+        Some(Ok(max_steps)) => Some(max_steps),
+        Some(Err(_)) => {
+            println!("Invalid --max-steps, expected a number");
+            return;
+        }
+        None => None,
+    };
+    let timeout = match take_flag(&mut args, "--timeout").map(|value| value.parse()) {
+        Some(Ok(seconds)) if seconds >= 0.0 => Some(Duration::from_secs_f64(seconds)),
+        Some(_) => {
+            println!("Invalid --timeout, expected a number of seconds");
+            return;
+        }
+        None => None,
+    };
+
+    // --history=FILE keeps the repl history in another file, --history= doesn't keep it
+    let history_file = match take_flag(&mut args, "--history") {
+        Some(path) if path.is_empty() => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => default_history_file(),
+    };
+
+    if let Err(err) = load_startup(&evaluator, &environment) {
+        println!("CRITICAL ERROR! Cannot load the base environment file 'env.lisp' due to the following error:");
+        print_eval_result_error(Err(err));
+        return;
+    }
+    let limits = Limits { max_steps, timeout };
+
+    if args.len() >= 2 {
+        let path = args.remove(1);
+        let content = std::fs::read_to_string(&path).unwrap();
+        limits.start(&evaluator);
+        run_source(content, &path, 1, &evaluator, &environment, None);
+    } else {
+        interrupt_on_ctrl_c(&evaluator);
+        repl::run_repl(&evaluator, &environment, history_file, limits);
+    }
+}
+
+// the step budget and the time given to an evaluation, none for no limit
+#[derive(Clone, Copy, Default)]
+struct Limits {
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+}
+
+impl Limits {
+    // gives the whole budget and time again, starting now. a ctrl-c pressed while nothing was
+    // running is dropped, it would stop the next evaluation at its first step.
+    fn start(&self, evaluator: &Evaluator) {
+        evaluator.interrupt_handle().store(false, Ordering::Relaxed);
+        evaluator.set_step_budget(self.max_steps);
+        evaluator.set_deadline(self.timeout.map(|timeout| Instant::now() + timeout));
+    }
+}
+
+// load initial environment via file parsing
+fn load_startup(evaluator: &Evaluator, environment: &SharedEnvironment) -> Result<(), EvalError> {
     let startup_code = std::fs::read_to_string("startup.lisp");
     let startup_code = match startup_code {
         Ok(s) => s,
@@ -26,33 +152,30 @@ fn main() {
         }
     };
 
-    let mut input = InputReader::new(Box::new(StringInputSource::new(startup_code)));
+    let mut input = InputReader::new(Box::new(StringInputSource::new(startup_code)))
+        .with_file_name("startup.lisp");
     let lexer = Lexer::create_lexer_iterator(&mut input);
     let mut parser = Parser::new(lexer);
-    let eval_result = run(&mut parser, &evaluator, environment.clone(), None);
-    if eval_result.is_err() {
-        println!("CRITICAL ERROR! Cannot load the base environment file 'env.lisp' due to the following error:");
-        print_eval_result_error(eval_result);
-        return;
-    }
-
-    let mut args: Vec<String> = std::env::args().collect();
-    let inputsource: Box<dyn InputSource> = if args.len() >= 2 {
-        let path = args.remove(1);
-        let content = std::fs::read_to_string(path).unwrap();
-        ast_printer = None;
-        Box::new(StringInputSource::new(content))
-    } else {
-        Box::new(REPLTerminalInputSource::new())
-    };
+    run(&mut parser, evaluator, environment.clone(), None)
+}
 
-    // start REPL
-    let mut input = InputReader::new(inputsource);
+// evaluates all the forms of the source, printing the errors and going on after them
+fn run_source(
+    content: String,
+    file_name: &str,
+    first_line: usize,
+    evaluator: &Evaluator,
+    environment: &SharedEnvironment,
+    printer: Option<&AstPrinter>,
+) {
+    let mut input = InputReader::new(Box::new(StringInputSource::new(content)))
+        .with_file_name(file_name)
+        .with_first_line(first_line);
     let lexer = Lexer::create_lexer_iterator(&mut input);
     let mut parser = Parser::new(lexer);
 
     loop {
-        let eval_result = run(&mut parser, &evaluator, environment.clone(), ast_printer);
+        let eval_result = run(&mut parser, evaluator, environment.clone(), printer);
         if eval_result.is_ok() {
             return;
         }
@@ -60,6 +183,29 @@ fn main() {
     }
 }
 
+// in the repl, ctrl-c stops the code running instead of killing the process with its definitions
+fn interrupt_on_ctrl_c(evaluator: &Evaluator) {
+    static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+    extern "C" fn on_sigint(_: libc::c_int) {
+        if let Some(interrupt) = INTERRUPT.get() {
+            interrupt.store(true, Ordering::Relaxed);
+        }
+    }
+    if INTERRUPT.set(evaluator.interrupt_handle()).is_ok() {
+        let handler: extern "C" fn(libc::c_int) = on_sigint;
+        unsafe {
+            libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+        }
+    }
+}
+
+// the value of a --name=value argument, removed from the arguments
+fn take_flag(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    let index = args.iter().position(|arg| arg.starts_with(&prefix))?;
+    Some(args.remove(index)[prefix.len()..].to_string())
+}
+
 fn run(
     parser: &mut Parser,
     evaluator: &Evaluator,
@@ -69,17 +215,18 @@ fn run(
     loop {
         match parser.read_form(true) {
             Ok(ast) => {
-                let eval_result = &evaluator.eval(ast, environment.clone())?;
+                let eval_result = &evaluator.eval(resolve(&ast), environment.clone())?;
                 if let Some(printer) = printer {
                     println!("{}", printer.ast_to_string(eval_result))
                 }
             }
             Err(ParsingError::EOF) => return Ok(()),
             Err(err) => {
+                parser.recover();
                 return Err(EvalError::custom_exception_str(format!(
                     "Parsing error: {:?}",
                     err
-                )))
+                )));
             }
         }
     }
@@ -88,5 +235,13 @@ fn run(
 fn print_eval_result_error(result: Result<(), EvalError>) {
     if let Err(err) = result {
         println!("Error: {}", err);
+        // innermost first. the trace only keeps the innermost frames of deep recursions
+        let stack_trace = err.stack_trace();
+        for frame in stack_trace.frames().iter().rev() {
+            println!("  at {}", frame);
+        }
+        if stack_trace.omitted() > 0 {
+            println!("  ... {} more", stack_trace.omitted());
+        }
     }
 }
@@ -1,15 +1,18 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 
-use crate::eval::{EnvironmentEntry, EvalError, SharedEnvironment};
+use im_rc::{HashMap, HashSet, Vector};
 
-use super::Lexer;
+use crate::eval::{CompiledBody, EnvironmentEntry, EvalError, SharedEnvironment};
+
+use super::{BigInt, HashMapKey, LexicalAddress, Sorted, SourceSpan, Symbol};
 
 #[derive(Clone)]
 pub struct LambdaEntry {
-    pub params: Vec<String>,
-    pub body: AstNode,
+    pub params: Vec<Symbol>,
+    pub body: Rc<AstNode>,
     pub env: SharedEnvironment,
     pub is_macro: bool,
+    pub code: Option<CompiledBody>, // the compiled body, for lambdas created by the compiling backends
 }
 
 impl LambdaEntry {
@@ -28,42 +31,86 @@ impl PartialEq for LambdaEntry {
     }
 }
 
+// user metadata attached with with-meta. it never takes part in comparisons.
+pub type Metadata = Option<Rc<AstNode>>;
+
 #[derive(Clone)]
 pub enum AstNode {
-    List(Vec<AstNode>),
-    Vector(Vec<AstNode>),
-    HashMap(HashMap<String, AstNode>),
+    List(Vector<AstNode>, Option<Rc<SourceSpan>>, Metadata), // the span is only set on forms coming from the parser
+    Vector(Vector<AstNode>, Metadata),
+    HashMap(HashMap<HashMapKey, AstNode>, Metadata),
+    Set(HashSet<HashMapKey>, Metadata),
+    SortedMap(Sorted<AstNode>, Metadata),
+    SortedSet(Sorted<()>, Metadata),
     Atom(Rc<RefCell<AstNode>>),
     String(String),
+    Keyword(String), // the name, without the leading ':'
     Int(i64),
+    BigInt(BigInt), // only for values that don't fit in an Int
+    Float(f64),
     Bool(bool),
     Nil,
-    FunctionPtr(Rc<EnvironmentEntry>), // internal only: a function pointer, like a lambda. saved in a variable
-    Lambda(Rc<LambdaEntry>),
-    UnresolvedSymbol(String), // only existing during parsing. Unresolved symbols get resolved into a function pointer during evaluation.
+    FunctionPtr(Rc<EnvironmentEntry>, Metadata), // internal only: a function pointer, like a lambda. saved in a variable
+    Lambda(Rc<LambdaEntry>, Metadata),
+    UnresolvedSymbol(Symbol, Option<LexicalAddress>), // the address of a local variable, if the resolver found one
 }
 
 impl PartialEq for AstNode {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (Self::List(l0), Self::List(r0)) => l0 == r0,
-            (Self::Vector(l0), Self::Vector(r0)) => l0 == r0,
-            (Self::Vector(l0), Self::List(r0)) => l0 == r0, // < list and vectors should be comparing equal
-            (Self::List(l0), Self::Vector(r0)) => l0 == r0, // < list and vectors should be comparing equal
-            (Self::HashMap(l0), Self::HashMap(r0)) => l0 == r0,
+            (Self::List(l0, _, _), Self::List(r0, _, _)) => l0 == r0,
+            (Self::Vector(l0, _), Self::Vector(r0, _)) => l0 == r0,
+            (Self::Vector(l0, _), Self::List(r0, _, _)) => l0 == r0, // < list and vectors should be comparing equal
+            (Self::List(l0, _, _), Self::Vector(r0, _)) => l0 == r0, // < list and vectors should be comparing equal
+            (Self::HashMap(l0, _), Self::HashMap(r0, _)) => l0 == r0,
+            (Self::Set(l0, _), Self::Set(r0, _)) => l0 == r0,
+            // sorted collections are equal to the unsorted ones with the same content
+            (Self::SortedMap(l0, _), Self::SortedMap(r0, _)) => entries_equal(l0.iter(), r0.iter()),
+            (Self::SortedMap(l0, _), Self::HashMap(r0, _)) => entries_equal(l0.iter(), r0.iter()),
+            (Self::HashMap(l0, _), Self::SortedMap(r0, _)) => entries_equal(l0.iter(), r0.iter()),
+            (Self::SortedSet(l0, _), Self::SortedSet(r0, _)) => {
+                elements_equal(l0.iter().map(|x| x.0), r0.iter().map(|x| x.0))
+            }
+            (Self::SortedSet(l0, _), Self::Set(r0, _)) => {
+                elements_equal(l0.iter().map(|x| x.0), r0.iter())
+            }
+            (Self::Set(l0, _), Self::SortedSet(r0, _)) => {
+                elements_equal(l0.iter(), r0.iter().map(|x| x.0))
+            }
             (Self::Atom(l0), Self::Atom(r0)) => l0 == r0,
             (Self::String(l0), Self::String(r0)) => l0 == r0,
+            (Self::Keyword(l0), Self::Keyword(r0)) => l0 == r0,
             (Self::Int(l0), Self::Int(r0)) => l0 == r0,
+            (Self::BigInt(l0), Self::BigInt(r0)) => l0 == r0,
+            (Self::Float(l0), Self::Float(r0)) => l0 == r0,
             (Self::Bool(l0), Self::Bool(r0)) => l0 == r0,
-            (Self::FunctionPtr(l0), Self::FunctionPtr(r0)) => l0 == r0,
-            (Self::Lambda(l0), Self::Lambda(r0)) => l0 == r0,
-            (Self::UnresolvedSymbol(l0), Self::UnresolvedSymbol(r0)) => l0 == r0,
+            (Self::FunctionPtr(l0, _), Self::FunctionPtr(r0, _)) => l0 == r0,
+            (Self::Lambda(l0, _), Self::Lambda(r0, _)) => l0 == r0,
+            (Self::UnresolvedSymbol(l0, _), Self::UnresolvedSymbol(r0, _)) => l0 == r0,
             (Self::Nil, Self::Nil) => true,
             _ => false,
         }
     }
 }
 
+fn entries_equal<'a>(
+    a: impl Iterator<Item = (&'a HashMapKey, &'a AstNode)>,
+    b: impl Iterator<Item = (&'a HashMapKey, &'a AstNode)>,
+) -> bool {
+    let a: HashMap<&HashMapKey, &AstNode> = a.collect();
+    let b: Vec<(&HashMapKey, &AstNode)> = b.collect();
+    a.len() == b.len() && b.iter().all(|(key, value)| a.get(key) == Some(value))
+}
+
+fn elements_equal<'a>(
+    a: impl Iterator<Item = &'a HashMapKey>,
+    b: impl Iterator<Item = &'a HashMapKey>,
+) -> bool {
+    let a: HashSet<&HashMapKey> = a.collect();
+    let b: Vec<&HashMapKey> = b.collect();
+    a.len() == b.len() && b.iter().all(|element| a.contains(element))
+}
+
 #[allow(dead_code)]
 impl AstNode {
     pub fn try_unwrap_int(self) -> Result<i64, EvalError> {
@@ -75,9 +122,18 @@ impl AstNode {
             }),
         }
     }
-    pub fn try_unwrap_list(self) -> Result<Vec<AstNode>, EvalError> {
+    pub fn try_unwrap_float(self) -> Result<f64, EvalError> {
+        match self {
+            AstNode::Float(f) => Ok(f),
+            v => Err(EvalError::TypeError {
+                expected: "Float".to_string(),
+                got: v,
+            }),
+        }
+    }
+    pub fn try_unwrap_list(self) -> Result<Vector<AstNode>, EvalError> {
         match self {
-            AstNode::List(i) => Ok(i),
+            AstNode::List(i, _, _) => Ok(i),
             v => Err(EvalError::TypeError {
                 expected: "List".to_string(),
                 got: v,
@@ -85,10 +141,10 @@ impl AstNode {
         }
     }
 
-    pub fn try_unwrap_list_or_vector(self) -> Result<Vec<AstNode>, EvalError> {
+    pub fn try_unwrap_list_or_vector(self) -> Result<Vector<AstNode>, EvalError> {
         match self {
-            AstNode::List(i) => Ok(i),
-            AstNode::Vector(i) => Ok(i),
+            AstNode::List(i, _, _) => Ok(i),
+            AstNode::Vector(i, _) => Ok(i),
             v => Err(EvalError::TypeError {
                 expected: "List or Vector".to_string(),
                 got: v,
@@ -96,24 +152,33 @@ impl AstNode {
         }
     }
 
-    pub fn try_unwrap_vector(self) -> Result<Vec<AstNode>, EvalError> {
+    pub fn try_unwrap_vector(self) -> Result<Vector<AstNode>, EvalError> {
         match self {
-            AstNode::Vector(i) => Ok(i),
+            AstNode::Vector(i, _) => Ok(i),
             v => Err(EvalError::TypeError {
                 expected: "Vector".to_string(),
                 got: v,
             }),
         }
     }
-    pub fn try_unwrap_hashmap(self) -> Result<HashMap<String, AstNode>, EvalError> {
+    pub fn try_unwrap_hashmap(self) -> Result<HashMap<HashMapKey, AstNode>, EvalError> {
         match self {
-            AstNode::HashMap(i) => Ok(i),
+            AstNode::HashMap(i, _) => Ok(i),
             v => Err(EvalError::TypeError {
                 expected: "Hashmap".to_string(),
                 got: v,
             }),
         }
     }
+    pub fn try_unwrap_set(self) -> Result<HashSet<HashMapKey>, EvalError> {
+        match self {
+            AstNode::Set(i, _) => Ok(i),
+            v => Err(EvalError::TypeError {
+                expected: "Set".to_string(),
+                got: v,
+            }),
+        }
+    }
     pub fn try_unwrap_atom(self) -> Result<Rc<RefCell<AstNode>>, EvalError> {
         match self {
             AstNode::Atom(i) => Ok(i),
@@ -134,13 +199,22 @@ impl AstNode {
     }
     pub fn try_unwrap_keyword(self) -> Result<String, EvalError> {
         match self {
-            AstNode::String(i) if i.starts_with(Lexer::KEYWORD_PREFIX) => Ok(i),
+            AstNode::Keyword(i) => Ok(i),
             v => Err(EvalError::TypeError {
                 expected: "Keyword".to_string(),
                 got: v,
             }),
         }
     }
+    pub fn try_unwrap_hashmap_key(self) -> Result<HashMapKey, EvalError> {
+        match HashMapKey::new(self.clone()) {
+            Some(key) => Ok(key),
+            None => Err(EvalError::TypeError {
+                expected: "hashable value".to_string(),
+                got: self,
+            }),
+        }
+    }
     pub fn try_unwrap_bool(self) -> Result<bool, EvalError> {
         match self {
             AstNode::Bool(i) => Ok(i),
@@ -159,9 +233,9 @@ impl AstNode {
             }),
         }
     }
-    pub fn try_unwrap_symbol(self) -> Result<String, EvalError> {
+    pub fn try_unwrap_symbol(self) -> Result<Symbol, EvalError> {
         match self {
-            AstNode::UnresolvedSymbol(name) => Ok(name),
+            AstNode::UnresolvedSymbol(name, _) => Ok(name),
             v => Err(EvalError::TypeError {
                 expected: "Symbol".to_string(),
                 got: v,
@@ -169,7 +243,50 @@ impl AstNode {
         }
     }
 
+    // keeps the invariant that integers fitting in an i64 are always an AstNode::Int
+    pub fn from_bigint(num: BigInt) -> AstNode {
+        match num.to_i64() {
+            Some(num) => AstNode::Int(num),
+            None => AstNode::BigInt(num),
+        }
+    }
+
+    // metadata attached with with-meta. values that can't hold metadata have none.
+    pub fn meta(&self) -> Metadata {
+        match self {
+            AstNode::List(_, _, meta)
+            | AstNode::Vector(_, meta)
+            | AstNode::HashMap(_, meta)
+            | AstNode::Set(_, meta)
+            | AstNode::SortedMap(_, meta)
+            | AstNode::SortedSet(_, meta)
+            | AstNode::FunctionPtr(_, meta)
+            | AstNode::Lambda(_, meta) => meta.clone(),
+            _ => None,
+        }
+    }
+
+    pub fn with_meta(self, meta: Metadata) -> Result<AstNode, EvalError> {
+        match self {
+            AstNode::List(list, span, _) => Ok(AstNode::List(list, span, meta)),
+            AstNode::Vector(vector, _) => Ok(AstNode::Vector(vector, meta)),
+            AstNode::HashMap(hashmap, _) => Ok(AstNode::HashMap(hashmap, meta)),
+            AstNode::Set(set, _) => Ok(AstNode::Set(set, meta)),
+            AstNode::SortedMap(map, _) => Ok(AstNode::SortedMap(map, meta)),
+            AstNode::SortedSet(set, _) => Ok(AstNode::SortedSet(set, meta)),
+            AstNode::FunctionPtr(fptr, _) => Ok(AstNode::FunctionPtr(fptr, meta)),
+            AstNode::Lambda(lambda, _) => Ok(AstNode::Lambda(lambda, meta)),
+            v => Err(EvalError::TypeError {
+                expected: "List/Vector/Hashmap/Set/Function".to_string(),
+                got: v,
+            }),
+        }
+    }
+
     pub fn create_keyword(name: &str) -> AstNode {
-        return AstNode::String(Lexer::KEYWORD_PREFIX.to_string() + name);
+        return AstNode::Keyword(name.to_string());
+    }
+    pub fn create_symbol(name: &str) -> AstNode {
+        AstNode::UnresolvedSymbol(Symbol::new(name), None)
     }
 }
@@ -2,7 +2,7 @@ use std::fmt::{Debug, Display};
 
 use string_builder::Builder;
 
-use super::{AstNode, Lexer};
+use super::{AstNode, HashMapKey};
 pub struct AstPrinter {
     format: AstPrintFormat,
 }
@@ -25,7 +25,7 @@ impl AstPrinter {
 
     fn append_form(&self, ast: &AstNode, builder: &mut Builder) {
         match ast {
-            AstNode::List(vec) => {
+            AstNode::List(vec, _, _) => {
                 builder.append("(");
                 let mut first_element = true;
                 for form in vec.iter() {
@@ -37,7 +37,7 @@ impl AstPrinter {
                 }
                 builder.append(")")
             }
-            AstNode::Vector(vec) => {
+            AstNode::Vector(vec, _) => {
                 builder.append("[");
                 let mut first_element = true;
                 for form in vec.iter() {
@@ -50,53 +50,85 @@ impl AstPrinter {
                 builder.append("]")
             }
             AstNode::Int(num) => builder.append(num.to_string()),
-            AstNode::UnresolvedSymbol(id) => builder.append(id.as_str()),
+            AstNode::BigInt(num) => builder.append(num.to_string()),
+            AstNode::Float(num) => self.append_float(*num, builder),
+            AstNode::UnresolvedSymbol(id, _) => builder.append(id.name()),
             AstNode::String(str) => match self.format {
                 AstPrintFormat::Readable => self.append_string_readable(str, builder),
                 AstPrintFormat::Repr => self.append_string_repr(str, builder),
             },
+            AstNode::Keyword(name) => {
+                builder.append(":");
+                builder.append(name.as_str())
+            }
             AstNode::Bool(true) => builder.append("true"),
             AstNode::Bool(false) => builder.append("false"),
             AstNode::Nil => builder.append("nil"),
-            AstNode::FunctionPtr(fptr) => builder.append(fptr.to_string()),
-            AstNode::Lambda(_) => builder.append("#<function>"),
+            AstNode::FunctionPtr(fptr, _) => builder.append(fptr.to_string()),
+            AstNode::Lambda(_, _) => builder.append("#<function>"),
             AstNode::Atom(atom) => {
                 builder.append("(atom ");
                 self.append_form(&(*atom.borrow()), builder);
                 builder.append(")");
             }
-            AstNode::HashMap(hm) => {
-                builder.append("{");
-                let mut first_element = true;
-                for item in hm {
-                    if !first_element {
-                        builder.append(" ");
-                    }
-                    self.append_form(&AstNode::String(item.0.clone()), builder);
-                    builder.append(" ");
-                    self.append_form(item.1, builder);
-                    first_element = false;
-                }
-                builder.append("}")
+            AstNode::Set(set, _) => self.append_elements(set.iter(), builder),
+            AstNode::SortedSet(set, _) => self.append_elements(set.iter().map(|x| x.0), builder),
+            AstNode::HashMap(hm, _) => self.append_entries(hm.iter(), builder),
+            AstNode::SortedMap(map, _) => self.append_entries(map.iter(), builder),
+        }
+    }
+
+    fn append_elements<'a>(
+        &self,
+        elements: impl Iterator<Item = &'a HashMapKey>,
+        builder: &mut Builder,
+    ) {
+        builder.append("#{");
+        let mut first_element = true;
+        for element in elements {
+            if !first_element {
+                builder.append(" ");
             }
+            self.append_form(&element.to_ast_node(), builder);
+            first_element = false;
         }
+        builder.append("}")
     }
 
-    fn append_string_readable(&self, str: &str, builder: &mut Builder) {
-        if str.starts_with(Lexer::KEYWORD_PREFIX) {
-            builder.append(":");
-            builder.append(&str[Lexer::KEYWORD_PREFIX.len()..]);
-            return;
+    fn append_entries<'a>(
+        &self,
+        entries: impl Iterator<Item = (&'a HashMapKey, &'a AstNode)>,
+        builder: &mut Builder,
+    ) {
+        builder.append("{");
+        let mut first_element = true;
+        for item in entries {
+            if !first_element {
+                builder.append(" ");
+            }
+            self.append_form(&item.0.to_ast_node(), builder);
+            builder.append(" ");
+            self.append_form(item.1, builder);
+            first_element = false;
+        }
+        builder.append("}")
+    }
+
+    // always printed so that the reader parses it back as a float: "1.0" and not "1"
+    fn append_float(&self, num: f64, builder: &mut Builder) {
+        if num.is_nan() {
+            builder.append("##NaN")
+        } else if num.is_infinite() {
+            builder.append(if num > 0.0 { "##Inf" } else { "##-Inf" })
         } else {
-            builder.append(str)
+            builder.append(format!("{:?}", num))
         }
     }
+
+    fn append_string_readable(&self, str: &str, builder: &mut Builder) {
+        builder.append(str)
+    }
     fn append_string_repr(&self, str: &str, builder: &mut Builder) {
-        if str.starts_with(Lexer::KEYWORD_PREFIX) {
-            builder.append(":");
-            builder.append(&str[Lexer::KEYWORD_PREFIX.len()..]);
-            return;
-        }
         builder.append('"');
         for ch in str.chars() {
             match ch {
@@ -0,0 +1,437 @@
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    ops::{Add, Mul, Neg, Sub},
+};
+
+// arbitrary precision integer, used when an i64 would overflow.
+// the magnitude is stored in base 2^32, least significant digit first, without trailing zeros.
+// zero is an empty magnitude and is never negative.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    fn new(negative: bool, mut magnitude: Vec<u32>) -> BigInt {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        BigInt {
+            negative: negative && !magnitude.is_empty(),
+            magnitude,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    // the number of bits of the magnitude, 0 for zero
+    pub fn bits(&self) -> u64 {
+        match self.magnitude.last() {
+            Some(last) => self.magnitude.len() as u64 * 32 - last.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    pub fn is_odd(&self) -> bool {
+        self.magnitude.first().is_some_and(|digit| digit & 1 == 1)
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+        let mut value: u64 = 0;
+        for digit in self.magnitude.iter().rev() {
+            value = (value << 32) | *digit as u64;
+        }
+        if self.negative {
+            if value <= i64::MAX as u64 + 1 {
+                Some((value as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else {
+            i64::try_from(value).ok()
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let value = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0.0, |acc, digit| acc * 4294967296.0 + *digit as f64);
+        if self.negative {
+            -value
+        } else {
+            value
+        }
+    }
+
+    // parses an optionally signed sequence of decimal digits
+    pub fn parse(text: &str) -> Option<BigInt> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        if digits.is_empty() || !digits.chars().all(|ch| ch.is_ascii_digit()) {
+            return None;
+        }
+
+        let mut magnitude = vec![];
+        for ch in digits.chars() {
+            mul_add_small(&mut magnitude, 10, ch.to_digit(10).unwrap());
+        }
+        Some(BigInt::new(negative, magnitude))
+    }
+
+    // exponentiation by squaring
+    pub fn pow(&self, mut exponent: u64) -> BigInt {
+        let mut result = BigInt::from(1);
+        let mut base = self.clone();
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = &result * &base;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    // division truncating towards zero, like i64. None when dividing by zero.
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = div_rem_magnitude(&self.magnitude, &other.magnitude);
+        Some((
+            BigInt::new(self.negative != other.negative, quotient),
+            BigInt::new(self.negative, remainder),
+        ))
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> Self {
+        let abs = value.unsigned_abs();
+        BigInt::new(value < 0, vec![abs as u32, (abs >> 32) as u32])
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // split in chunks of 9 decimal digits, least significant first
+        let mut chunks = vec![];
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            chunks.push(div_rem_small(&mut magnitude, 1_000_000_000));
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.magnitude, &other.magnitude),
+            (true, true) => cmp_magnitude(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.magnitude.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(
+                self.negative,
+                add_magnitude(&self.magnitude, &other.magnitude),
+            );
+        }
+        // different signs: subtract the smaller magnitude from the bigger one
+        match cmp_magnitude(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::new(
+                other.negative,
+                sub_magnitude(&other.magnitude, &self.magnitude),
+            ),
+            _ => BigInt::new(
+                self.negative,
+                sub_magnitude(&self.magnitude, &other.magnitude),
+            ),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &(-other)
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut result = vec![0u32; self.magnitude.len() + other.magnitude.len()];
+        for (i, a) in self.magnitude.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, b) in other.magnitude.iter().enumerate() {
+                let current = result[i + j] as u64 + (*a as u64) * (*b as u64) + carry;
+                result[i + j] = current as u32;
+                carry = current >> 32;
+            }
+            result[i + other.magnitude.len()] = carry as u32;
+        }
+        BigInt::new(self.negative != other.negative, result)
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for i in 0..a.len().max(b.len()) {
+        let current = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(current as u32);
+        carry = current >> 32;
+    }
+    result.push(carry as u32);
+    result
+}
+
+// requires a >= b
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, digit) in a.iter().enumerate() {
+        let mut current = *digit as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if current < 0 {
+            current += 1 << 32;
+            borrow = 1;
+        }
+        result.push(current as u32);
+    }
+    result
+}
+
+fn mul_add_small(magnitude: &mut Vec<u32>, factor: u32, addend: u32) {
+    let mut carry = addend as u64;
+    for digit in magnitude.iter_mut() {
+        let current = (*digit as u64) * factor as u64 + carry;
+        *digit = current as u32;
+        carry = current >> 32;
+    }
+    if carry != 0 {
+        magnitude.push(carry as u32);
+    }
+}
+
+// divides in place, returning the remainder
+fn div_rem_small(magnitude: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut remainder = 0u64;
+    for digit in magnitude.iter_mut().rev() {
+        let current = (remainder << 32) | *digit as u64;
+        *digit = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+    remainder as u32
+}
+
+// schoolbook binary long division. b must not be zero.
+fn div_rem_magnitude(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if b.len() == 1 {
+        let mut quotient = a.to_vec();
+        let remainder = div_rem_small(&mut quotient, b[0]);
+        return (quotient, vec![remainder]);
+    }
+    if cmp_magnitude(a, b) == Ordering::Less {
+        return (vec![], a.to_vec());
+    }
+
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = vec![];
+    for bit in (0..a.len() * 32).rev() {
+        // remainder = remainder * 2 + next bit of a
+        mul_add_small(&mut remainder, 2, (a[bit / 32] >> (bit % 32)) & 1);
+        if cmp_magnitude(&remainder, b) != Ordering::Less {
+            remainder = sub_magnitude(&remainder, b);
+            while remainder.last() == Some(&0) {
+                remainder.pop();
+            }
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+    (quotient, remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // values around the 2^32 digit and the i64/u64 boundaries, checked against i128
+    fn interesting() -> Vec<i128> {
+        let mut values = vec![0, 1, 2, 7];
+        for base in [1i128 << 32, 1i128 << 63, 1i128 << 64, 1i128 << 95] {
+            values.extend([base - 1, base, base + 1]);
+        }
+        values.extend([12345678901234567890123456789, 1 << 100]);
+        let negatives: Vec<i128> = values.iter().map(|value| -value).collect();
+        values.extend(negatives);
+        values
+    }
+
+    fn big(value: i128) -> BigInt {
+        BigInt::parse(&value.to_string()).unwrap()
+    }
+
+    #[test]
+    fn display_matches_i128() {
+        for value in interesting() {
+            assert_eq!(big(value).to_string(), value.to_string());
+        }
+        assert_eq!(BigInt::from(i64::MIN).to_string(), i64::MIN.to_string());
+        assert_eq!(BigInt::from(i64::MAX).to_string(), i64::MAX.to_string());
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        assert_eq!(BigInt::parse("-0").unwrap().to_string(), "0");
+        assert!(!BigInt::parse("-0").unwrap().is_negative());
+        assert_eq!(BigInt::parse("+5").unwrap(), BigInt::from(5));
+        assert_eq!(
+            BigInt::parse("00018446744073709551616").unwrap(),
+            big(1 << 64)
+        );
+        let text = "-123456789012345678901234567890123456789012345678901234567890";
+        assert_eq!(BigInt::parse(text).unwrap().to_string(), text);
+        for invalid in ["", "-", "+", "1a", "1 2", "--1"] {
+            assert_eq!(BigInt::parse(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn add_sub_carry_and_borrow() {
+        for a in interesting() {
+            for b in interesting() {
+                if let Some(sum) = a.checked_add(b) {
+                    assert_eq!(&big(a) + &big(b), big(sum), "{} + {}", a, b);
+                }
+                if let Some(difference) = a.checked_sub(b) {
+                    assert_eq!(&big(a) - &big(b), big(difference), "{} - {}", a, b);
+                }
+            }
+            assert_eq!(-&big(a), big(-a));
+        }
+    }
+
+    #[test]
+    fn mul_carry() {
+        for a in interesting() {
+            for b in interesting() {
+                if let Some(product) = a.checked_mul(b) {
+                    assert_eq!(&big(a) * &big(b), big(product), "{} * {}", a, b);
+                }
+            }
+        }
+        let two_64 = big(1 << 64);
+        assert_eq!(
+            (&two_64 * &two_64).to_string(),
+            "340282366920938463463374607431768211456"
+        );
+        assert_eq!(BigInt::from(2).pow(64), two_64);
+    }
+
+    #[test]
+    fn div_rem_truncates_towards_zero() {
+        for a in interesting() {
+            for b in interesting() {
+                let result = big(a).div_rem(&big(b));
+                if b == 0 {
+                    assert_eq!(result, None);
+                } else {
+                    let (quotient, remainder) = result.unwrap();
+                    assert_eq!(quotient, big(a / b), "{} / {}", a, b);
+                    assert_eq!(remainder, big(a % b), "{} % {}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn to_i64_boundaries() {
+        assert_eq!(big(i64::MAX as i128).to_i64(), Some(i64::MAX));
+        assert_eq!(big(i64::MIN as i128).to_i64(), Some(i64::MIN));
+        assert_eq!(big(i64::MAX as i128 + 1).to_i64(), None);
+        assert_eq!(big(i64::MIN as i128 - 1).to_i64(), None);
+        assert_eq!(big(-(1 << 64)).to_i64(), None);
+    }
+
+    #[test]
+    fn bits() {
+        for value in interesting() {
+            let expected = 128 - value.unsigned_abs().leading_zeros() as u64;
+            assert_eq!(big(value).bits(), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn ordering() {
+        let mut values = interesting();
+        let mut bigs: Vec<BigInt> = values.iter().map(|value| big(*value)).collect();
+        values.sort();
+        bigs.sort();
+        let expected: Vec<BigInt> = values.iter().map(|value| big(*value)).collect();
+        assert_eq!(bigs, expected);
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use super::AstNode;
+
+// a value used as a hash-map key or set element.
+// hashing follows AstNode's PartialEq, so lists and vectors with the same elements are the same key.
+// atoms and functions can't be keys: they are mutable or only comparable by identity.
+#[derive(Clone, Debug)]
+pub struct HashMapKey(AstNode);
+
+impl HashMapKey {
+    pub fn new(node: AstNode) -> Option<HashMapKey> {
+        if Self::is_hashable(&node) {
+            Some(HashMapKey(node))
+        } else {
+            None
+        }
+    }
+
+    pub fn keyword(name: &str) -> HashMapKey {
+        HashMapKey(AstNode::create_keyword(name))
+    }
+
+    pub fn to_ast_node(&self) -> AstNode {
+        self.0.clone()
+    }
+
+    pub fn into_ast_node(self) -> AstNode {
+        self.0
+    }
+
+    fn is_hashable(node: &AstNode) -> bool {
+        match node {
+            AstNode::List(elements, _, _) | AstNode::Vector(elements, _) => {
+                elements.iter().all(Self::is_hashable)
+            }
+            // nested map keys and set elements were already checked when they were inserted
+            AstNode::HashMap(hm, _) => hm.values().all(Self::is_hashable),
+            AstNode::SortedMap(map, _) => map.iter().all(|(_, value)| Self::is_hashable(value)),
+            AstNode::Set(_, _) | AstNode::SortedSet(_, _) => true,
+            AstNode::Atom(_) | AstNode::FunctionPtr(_, _) | AstNode::Lambda(_, _) => false,
+            _ => true,
+        }
+    }
+}
+
+fn hash_node<H: Hasher>(node: &AstNode, state: &mut H) {
+    match node {
+        // same tag for lists and vectors, as they compare equal
+        AstNode::List(elements, _, _) | AstNode::Vector(elements, _) => {
+            0u8.hash(state);
+            elements.len().hash(state);
+            for element in elements {
+                hash_node(element, state);
+            }
+        }
+        // the iteration order of maps and sets is random, and sorted collections are equal to
+        // unsorted ones with the same content, so they hash with an order independent sum.
+        AstNode::HashMap(hm, _) => hash_entries(hm.iter(), state),
+        AstNode::SortedMap(map, _) => hash_entries(map.iter(), state),
+        AstNode::Set(set, _) => hash_elements(set.iter(), state),
+        AstNode::SortedSet(set, _) => hash_elements(set.iter().map(|x| x.0), state),
+        AstNode::String(str) => {
+            2u8.hash(state);
+            str.hash(state);
+        }
+        AstNode::Keyword(name) => {
+            3u8.hash(state);
+            name.hash(state);
+        }
+        AstNode::Int(num) => {
+            4u8.hash(state);
+            num.hash(state);
+        }
+        AstNode::BigInt(num) => {
+            5u8.hash(state);
+            num.hash(state);
+        }
+        AstNode::Float(num) => {
+            // 0.0 and -0.0 are equal, so they must hash the same
+            6u8.hash(state);
+            if *num == 0.0 {
+                0.0f64.to_bits().hash(state);
+            } else {
+                num.to_bits().hash(state);
+            }
+        }
+        AstNode::Bool(b) => {
+            7u8.hash(state);
+            b.hash(state);
+        }
+        AstNode::Nil => 8u8.hash(state),
+        AstNode::UnresolvedSymbol(name, _) => {
+            9u8.hash(state);
+            name.hash(state);
+        }
+        // never stored in a key, see is_hashable
+        AstNode::Atom(_) | AstNode::FunctionPtr(_, _) | AstNode::Lambda(_, _) => {
+            unreachable!()
+        }
+    }
+}
+
+fn hash_entries<'a, H: Hasher>(
+    entries: impl Iterator<Item = (&'a HashMapKey, &'a AstNode)>,
+    state: &mut H,
+) {
+    1u8.hash(state);
+    let (mut len, mut sum) = (0usize, 0u64);
+    for (key, value) in entries {
+        let mut entry_hasher = DefaultHasher::new();
+        key.hash(&mut entry_hasher);
+        hash_node(value, &mut entry_hasher);
+        sum = sum.wrapping_add(entry_hasher.finish());
+        len += 1;
+    }
+    len.hash(state);
+    sum.hash(state);
+}
+
+fn hash_elements<'a, H: Hasher>(elements: impl Iterator<Item = &'a HashMapKey>, state: &mut H) {
+    10u8.hash(state);
+    let (mut len, mut sum) = (0usize, 0u64);
+    for element in elements {
+        let mut element_hasher = DefaultHasher::new();
+        element.hash(&mut element_hasher);
+        sum = sum.wrapping_add(element_hasher.finish());
+        len += 1;
+    }
+    len.hash(state);
+    sum.hash(state);
+}
+
+impl Hash for HashMapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_node(&self.0, state)
+    }
+}
+
+impl PartialEq for HashMapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+// not strictly true for ##NaN keys, which can be inserted but never found again
+impl Eq for HashMapKey {}
@@ -1,6 +1,6 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, rc::Rc};
 
-use super::InputSource;
+use super::{InputSource, SourceSpan};
 
 pub struct InputReader {
     // fields used by refill_buffer and read_char
@@ -10,6 +10,11 @@ pub struct InputReader {
     // get_char / peek_char support
     peeked_char: Option<char>,
 
+    // position of the next char returned by get_char
+    file: Option<Rc<str>>,
+    line: usize,
+    column: usize,
+
     input_source: Box<dyn InputSource>,
 }
 
@@ -60,20 +65,54 @@ impl InputReader {
         Ok(self.peeked_char.unwrap())
     }
     pub fn get_char(&mut self) -> Result<char, InputError> {
-        if let Some(c) = self.peeked_char {
+        let c = if let Some(c) = self.peeked_char {
             self.peeked_char = None;
-            Ok(c)
+            c
+        } else {
+            self.read_char()?
+        };
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            self.read_char()
+            self.column += 1;
+        }
+        Ok(c)
+    }
+
+    // drops what is left of the input read so far, keeping the position up to date
+    pub fn discard_buffered(&mut self) {
+        while self.peeked_char.is_some() || !self.buffer.is_empty() {
+            let _ = self.get_char();
         }
     }
 
+    // position of the next character that will be returned by get_char
+    pub fn position(&self) -> SourceSpan {
+        SourceSpan::new(self.file.clone(), self.line, self.column)
+    }
+
     pub fn new(input_source: Box<dyn InputSource>) -> InputReader {
         return InputReader {
             buffer: VecDeque::new(),
             end: false,
             peeked_char: None,
+            file: None,
+            line: 1,
+            column: 1,
             input_source,
         };
     }
+
+    pub fn with_file_name(mut self, file: &str) -> Self {
+        self.file = Some(Rc::from(file));
+        self
+    }
+
+    // for input that continues a source read before, like an entry in the repl
+    pub fn with_first_line(mut self, line: usize) -> Self {
+        self.line = line;
+        self
+    }
 }
@@ -1,35 +1,164 @@
-use rustyline::{error::ReadlineError, history::FileHistory, DefaultEditor, Editor};
+use std::{collections::HashSet, path::PathBuf};
 
-use super::InputError;
+use rustyline::{
+    error::ReadlineError,
+    history::{FileHistory, History},
+    Cmd, Config, Editor, KeyCode, KeyEvent, Modifiers,
+};
+
+use crate::eval::SharedEnvironment;
+
+use super::{repl_helper::is_incomplete, InputError, ReplHelper};
 
 pub trait InputSource {
     fn read(&mut self) -> Result<String, InputError>;
 }
 
+// the number of entries kept in the history
+const HISTORY_SIZE: usize = 1000;
+
 pub struct REPLTerminalInputSource {
-    rustyline: Editor<(), FileHistory>,
+    rustyline: Editor<ReplHelper, FileHistory>,
+    config: Config,
+    // where the history is saved after each entry, if it's kept
+    history_file: Option<PathBuf>,
+    // ctrl-d was pressed in the middle of an entry
+    eof: bool,
 }
 
 impl InputSource for REPLTerminalInputSource {
+    // reads lines until the entry has no unclosed string or brackets
     fn read(&mut self) -> Result<String, InputError> {
-        let read_result = self.rustyline.readline("user> ");
-
-        match read_result {
-            Ok(str) => Ok(str + "\n"),
-            Err(ReadlineError::Eof) => Err(InputError::ExitIndication),
-            Err(ReadlineError::Interrupted) => Err(InputError::ExitIndication),
-            Err(ReadlineError::WindowResized) => Err(InputError::RetriableError),
-            Err(_) => Err(InputError::NonRetriableError),
+        if self.eof {
+            return Err(InputError::ExitIndication);
+        }
+
+        let mut entry = String::new();
+        loop {
+            let prompt = if entry.is_empty() {
+                "user> "
+            } else {
+                "  ...> "
+            };
+
+            match self.rustyline.readline(prompt) {
+                Ok(line) => {
+                    entry += &line;
+                    entry.push('\n');
+                    if !is_incomplete(&entry) {
+                        self.remember(entry.trim_end());
+                        return Ok(entry);
+                    }
+                }
+                Err(ReadlineError::Eof) if entry.is_empty() => {
+                    return Err(InputError::ExitIndication)
+                }
+                // the unfinished entry is still parsed, to report what is missing
+                Err(ReadlineError::Eof) => {
+                    self.eof = true;
+                    return Ok(entry);
+                }
+                // ctrl-c clears the line, or cancels the unfinished entry
+                Err(ReadlineError::Interrupted) => entry.clear(),
+                Err(ReadlineError::WindowResized) if entry.is_empty() => {
+                    return Err(InputError::RetriableError)
+                }
+                Err(ReadlineError::WindowResized) => {}
+                Err(_) => return Err(InputError::NonRetriableError),
+            }
         }
     }
 }
 
 impl REPLTerminalInputSource {
-    pub fn new() -> Self {
+    pub fn new(environment: SharedEnvironment, history_file: Option<PathBuf>) -> Self {
+        let config = Config::builder()
+            .max_history_size(HISTORY_SIZE)
+            .unwrap()
+            .history_ignore_dups(true)
+            .unwrap()
+            .build();
+        let mut rustyline = Editor::with_config(config).unwrap();
+        rustyline.set_helper(Some(ReplHelper::new(environment)));
+
+        // page up and page down search the entries starting like the text before the cursor,
+        // ctrl-r searches anywhere in them
+        rustyline.bind_sequence(
+            KeyEvent(KeyCode::PageUp, Modifiers::NONE),
+            Cmd::HistorySearchBackward,
+        );
+        rustyline.bind_sequence(
+            KeyEvent(KeyCode::PageDown, Modifiers::NONE),
+            Cmd::HistorySearchForward,
+        );
+
+        if let Some(path) = &history_file {
+            if let Some(dir) = path.parent() {
+                let _ = std::fs::create_dir_all(dir);
+            }
+            match rustyline.load_history(path) {
+                Ok(()) => {}
+                Err(ReadlineError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => println!("Cannot load the history from '{}': {}", path.display(), err),
+            }
+            remove_duplicates(rustyline.history_mut());
+        }
+
         Self {
-            rustyline: DefaultEditor::new().unwrap(),
+            rustyline,
+            config,
+            history_file,
+            eof: false,
         }
     }
+
+    // adds a complete entry to the history, multi-line forms as one entry.
+    // an entry that is already there is moved to the end instead of being repeated.
+    fn remember(&mut self, entry: &str) {
+        let history = self.rustyline.history_mut();
+        let _ = history.add(entry);
+        remove_duplicates(history);
+
+        let Some(path) = &self.history_file else {
+            return;
+        };
+        // only the new entry is appended, the file is locked and merged with the entries saved
+        // by other sessions meanwhile. it can repeat entries, they are removed when it's loaded.
+        let mut new_entry = FileHistory::with_config(self.config);
+        let _ = new_entry.add(entry);
+        if let Err(err) = new_entry.append(path) {
+            println!("Cannot save the history to '{}': {}", path.display(), err);
+            self.history_file = None;
+        }
+    }
+}
+
+// keeps the last occurrence of each entry
+fn remove_duplicates(history: &mut FileHistory) {
+    let mut seen = HashSet::new();
+    let mut kept: Vec<String> = history
+        .iter()
+        .rev()
+        .filter(|entry| seen.insert(entry.as_str()))
+        .cloned()
+        .collect();
+    if kept.len() == history.len() {
+        return;
+    }
+    kept.reverse();
+    let _ = history.clear();
+    for entry in kept {
+        let _ = history.add_owned(entry);
+    }
+}
+
+// $XDG_STATE_HOME/myrust/history, or ~/.local/state/myrust/history
+pub fn default_history_file() -> Option<PathBuf> {
+    let state = match std::env::var_os("XDG_STATE_HOME") {
+        Some(state) if !state.is_empty() => PathBuf::from(state),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state.join("myrust").join("history"))
 }
 
 pub struct StringInputSource {
@@ -1,4 +1,4 @@
-use super::{InputError, InputReader};
+use super::{InputError, InputReader, SourceSpan};
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum LexToken {
@@ -8,16 +8,26 @@ pub enum LexToken {
     SquareParenClose,
     CurlyParenOpen,
     CurlyParenClose,
+    HashCurlyParenOpen,   // #{
     Tick,                 // '
     BackTick,             // `
     TildeAt,              // ~@
     Tilde,                // ~
     At,                   // @
+    Caret,                // ^
     QuotedString(String), // "hello"
+    Keyword(String),      // :keyword
     Comment(String),      // ; this is a comment
     Name(String),         // true 10 nil anothername
 }
 
+// a token together with the position where it starts in the source
+#[derive(PartialEq, Debug, Clone)]
+pub struct SpannedToken {
+    pub token: LexToken,
+    pub span: SourceSpan,
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum LexingError {
     InputError(InputError),
@@ -26,8 +36,6 @@ pub enum LexingError {
 pub struct Lexer {}
 
 impl Lexer {
-    pub const KEYWORD_PREFIX: &str = " :KEYWORD:";
-
     fn read_string_token(reader: &mut InputReader) -> Result<LexToken, LexingError> {
         let mut str = String::new();
         loop {
@@ -68,17 +76,19 @@ impl Lexer {
         }
     }
     fn read_keyword(reader: &mut InputReader) -> Result<LexToken, LexingError> {
-        let mut str = Self::KEYWORD_PREFIX.to_string();
+        let mut str = String::new();
         let specials = vec!['(', ')', '[', ']', '{', '}', ','];
 
         loop {
             match reader.peek_char() {
                 Ok(ch) if ch.is_whitespace() || specials.contains(&ch) => {
-                    return Ok(LexToken::QuotedString(str))
+                    return Ok(LexToken::Keyword(str))
                 }
                 Ok(_) => str.push(reader.get_char()?),
                 Err(InputError::RetriableError) => continue,
-                Err(InputError::ExitIndication) if str.len() > 0 => return Ok(LexToken::Name(str)),
+                Err(InputError::ExitIndication) if str.len() > 0 => {
+                    return Ok(LexToken::Keyword(str))
+                }
                 Err(err) => return Err(err.into()),
             }
         }
@@ -95,8 +105,9 @@ impl Lexer {
             }
         }
     }
-    pub fn read_next_token(reader: &mut InputReader) -> Result<LexToken, LexingError> {
+    pub fn read_next_token(reader: &mut InputReader) -> Result<SpannedToken, LexingError> {
         loop {
+            let span = reader.position();
             let char = reader.get_char()?;
             let res = match char {
                 // eat up all whitespace inbetween terms
@@ -118,13 +129,18 @@ impl Lexer {
                         LexToken::Tilde
                     }
                 }
+                '#' if reader.peek_char() == Ok('{') => {
+                    reader.get_char()?;
+                    LexToken::HashCurlyParenOpen
+                }
                 '@' => LexToken::At,
+                '^' => LexToken::Caret,
                 '"' => Self::read_string_token(reader)?,
                 ';' => Self::read_comment(reader)?,
                 ':' => Self::read_keyword(reader)?,
                 ch => Self::read_name(ch, reader)?,
             };
-            return Ok(res);
+            return Ok(SpannedToken { token: res, span });
         }
     }
 
@@ -145,10 +161,14 @@ impl<'a> LexerIterator<'a> {
             eof_reached: false,
         }
     }
+
+    pub fn discard_buffered(&mut self) {
+        self.input.discard_buffered();
+    }
 }
 
 impl<'a> Iterator for LexerIterator<'a> {
-    type Item = Result<LexToken, LexingError>;
+    type Item = Result<SpannedToken, LexingError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.eof_reached {
@@ -1,14 +1,26 @@
 mod ast;
 mod ast_printer;
+mod bigint;
+mod hashmap_key;
 mod input;
 mod inputsource;
 mod lexer;
 mod parser;
+mod repl_helper;
+mod sorted;
+mod span;
+mod symbol;
 
 pub use ast::AstNode;
 pub use ast::LambdaEntry;
 pub use ast_printer::{AstPrintFormat, AstPrinter};
+pub use bigint::BigInt;
+pub use hashmap_key::HashMapKey;
 pub use input::{InputError, InputReader};
 pub use inputsource::*;
 pub use lexer::*;
 pub use parser::*;
+pub use repl_helper::{call_signature, ReplHelper};
+pub use sorted::Sorted;
+pub use span::SourceSpan;
+pub use symbol::{LexicalAddress, Symbol};
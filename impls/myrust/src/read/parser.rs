@@ -1,10 +1,12 @@
-use super::AstNode;
-use super::{LexToken, LexerIterator, LexingError};
-use std::collections::HashMap;
-use std::iter::Peekable;
+use super::{AstNode, BigInt, HashMapKey};
+use super::{LexToken, LexerIterator, LexingError, SourceSpan, SpannedToken};
+use im_rc::{vector, HashMap, HashSet, Vector};
+use std::rc::Rc;
 
 pub struct Parser<'a> {
-    lexer: Peekable<LexerIterator<'a>>,
+    lexer: LexerIterator<'a>,
+    // the next token once it was peeked, None inside means the end of the input
+    peeked: Option<Option<Result<SpannedToken, LexingError>>>,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -23,74 +25,113 @@ impl From<LexingError> for ParsingError {
 impl<'a> Parser<'a> {
     pub fn new(lexer: LexerIterator<'a>) -> Self {
         Self {
-            lexer: lexer.peekable(),
+            lexer,
+            peeked: None,
         }
     }
 
+    // after a parsing error, skips the rest of the input read so far (in the repl, the rest of
+    // the entry), so that the next form isn't read from the middle of the broken one
+    pub fn recover(&mut self) {
+        self.peeked = None;
+        self.lexer.discard_buffered();
+    }
+
     pub fn read_form(&mut self, eof_allowed: bool) -> Result<AstNode, ParsingError> {
         use super::LexToken::*;
 
+        let span = self.peek_span();
         match self.peek_token() {
             Ok(tok) => match tok {
                 RoundParenOpen => {
                     self.get_token()?;
                     Ok(AstNode::List(
                         self.read_form_list(LexToken::RoundParenClose)?,
+                        span,
+                        None,
                     ))
                 }
                 SquareParenOpen => {
                     self.get_token()?;
                     Ok(AstNode::Vector(
                         self.read_form_list(LexToken::SquareParenClose)?,
+                        None,
                     ))
                 }
                 CurlyParenOpen => {
                     self.get_token()?;
                     Ok(self.read_hashmap()?)
                 }
+                HashCurlyParenOpen => {
+                    self.get_token()?;
+                    Ok(self.read_set()?)
+                }
                 Tick => {
                     self.get_token()?;
-                    Ok(AstNode::List(vec![
-                        AstNode::UnresolvedSymbol("quote".to_string()),
-                        self.read_form(false)?,
-                    ]))
+                    Ok(AstNode::List(
+                        vector![AstNode::create_symbol("quote"), self.read_form(false)?,],
+                        span,
+                        None,
+                    ))
                 }
                 BackTick => {
                     self.get_token()?;
-                    Ok(AstNode::List(vec![
-                        AstNode::UnresolvedSymbol("quasiquote".to_string()),
-                        self.read_form(false)?,
-                    ]))
+                    Ok(AstNode::List(
+                        vector![AstNode::create_symbol("quasiquote"), self.read_form(false)?,],
+                        span,
+                        None,
+                    ))
                 }
                 Tilde => {
                     self.get_token()?;
-                    Ok(AstNode::List(vec![
-                        AstNode::UnresolvedSymbol("unquote".to_string()),
-                        self.read_form(false)?,
-                    ]))
+                    Ok(AstNode::List(
+                        vector![AstNode::create_symbol("unquote"), self.read_form(false)?,],
+                        span,
+                        None,
+                    ))
                 }
                 TildeAt => {
                     self.get_token()?;
-                    Ok(AstNode::List(vec![
-                        AstNode::UnresolvedSymbol("splice-unquote".to_string()),
-                        self.read_form(false)?,
-                    ]))
+                    Ok(AstNode::List(
+                        vector![
+                            AstNode::create_symbol("splice-unquote"),
+                            self.read_form(false)?,
+                        ],
+                        span,
+                        None,
+                    ))
                 }
                 At => {
                     self.get_token()?;
-                    Ok(AstNode::List(vec![
-                        AstNode::UnresolvedSymbol("deref".to_string()),
-                        self.read_form(false)?,
-                    ]))
+                    Ok(AstNode::List(
+                        vector![AstNode::create_symbol("deref"), self.read_form(false)?,],
+                        span,
+                        None,
+                    ))
+                }
+                Caret => {
+                    // ^meta form is read as (with-meta form meta)
+                    self.get_token()?;
+                    let meta = self.read_form(false)?;
+                    Ok(AstNode::List(
+                        vector![
+                            AstNode::create_symbol("with-meta"),
+                            self.read_form(false)?,
+                            meta,
+                        ],
+                        span,
+                        None,
+                    ))
                 }
                 QuotedString(_) => self.read_atom(),
+                Keyword(_) => self.read_atom(),
                 Comment(_) => {
                     self.get_token()?;
                     self.read_form(eof_allowed) // read next form (RECURSIVE! hoping for tail call optimization here)
                 }
                 Name(_) => self.read_atom(),
                 unexpected => {
-                    self.lexer.next();
+                    self.next();
                     Err(ParsingError::UnexpectedToken(unexpected))
                 }
             },
@@ -98,22 +139,36 @@ impl<'a> Parser<'a> {
             Err(err) => Err(err),
         }
     }
+    fn peek(&mut self) -> Option<&Result<SpannedToken, LexingError>> {
+        let lexer = &mut self.lexer;
+        self.peeked.get_or_insert_with(|| lexer.next()).as_ref()
+    }
+    fn next(&mut self) -> Option<Result<SpannedToken, LexingError>> {
+        self.peeked.take().unwrap_or_else(|| self.lexer.next())
+    }
     fn peek_token(&mut self) -> Result<LexToken, ParsingError> {
-        let res = self.lexer.peek().ok_or(ParsingError::UnexpectedEOF)?;
+        let res = self.peek().ok_or(ParsingError::UnexpectedEOF)?;
         match res {
-            Ok(res) => Ok(res.clone()),
+            Ok(res) => Ok(res.token.clone()),
             Err(err) => Err(err.clone().into()),
         }
     }
+    // position of the next token, if there is one
+    fn peek_span(&mut self) -> Option<Rc<SourceSpan>> {
+        match self.peek() {
+            Some(Ok(res)) => Some(Rc::new(res.span.clone())),
+            _ => None,
+        }
+    }
     fn get_token(&mut self) -> Result<LexToken, ParsingError> {
-        let res = self.lexer.next().ok_or(ParsingError::UnexpectedEOF)?;
-        res.map_err(|err| err.into())
+        let res = self.next().ok_or(ParsingError::UnexpectedEOF)?;
+        res.map(|res| res.token).map_err(|err| err.into())
     }
 
-    fn read_form_list(&mut self, until: LexToken) -> Result<Vec<AstNode>, ParsingError> {
-        let mut result = vec![];
+    fn read_form_list(&mut self, until: LexToken) -> Result<Vector<AstNode>, ParsingError> {
+        let mut result = Vector::new();
         while self.peek_token()? != until {
-            result.push(self.read_form(false)?)
+            result.push_back(self.read_form(false)?)
         }
 
         self.get_token()?; // get the "until" token
@@ -123,9 +178,14 @@ impl<'a> Parser<'a> {
     fn read_atom(&mut self) -> Result<AstNode, ParsingError> {
         match self.get_token()? {
             LexToken::QuotedString(str) => Ok(AstNode::String(str)),
+            LexToken::Keyword(name) => Ok(AstNode::Keyword(name)),
             LexToken::Name(name) => {
                 if let Ok(num) = name.parse() {
                     Ok(AstNode::Int(num))
+                } else if let Some(num) = BigInt::parse(&name) {
+                    Ok(AstNode::BigInt(num))
+                } else if let Some(num) = Self::parse_float(&name) {
+                    Ok(AstNode::Float(num))
                 } else {
                     if name == "nil" {
                         Ok(AstNode::Nil)
@@ -134,7 +194,7 @@ impl<'a> Parser<'a> {
                     } else if name == "false" {
                         Ok(AstNode::Bool(false))
                     } else {
-                        Ok(AstNode::UnresolvedSymbol(name))
+                        Ok(AstNode::create_symbol(&name))
                     }
                 }
             }
@@ -142,21 +202,50 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // accepts "1.5", "-.5", "1e10", "2.5E-3" and the special values ##Inf, ##-Inf, ##NaN.
+    // names like "inf" or "nan" are symbols, even if rust would parse them as floats.
+    fn parse_float(name: &str) -> Option<f64> {
+        match name {
+            "##Inf" => return Some(f64::INFINITY),
+            "##-Inf" => return Some(f64::NEG_INFINITY),
+            "##NaN" => return Some(f64::NAN),
+            _ => {}
+        }
+
+        let digits = name.trim_start_matches(['+', '-']);
+        let starts_like_number = match digits.chars().next() {
+            Some(ch) if ch.is_ascii_digit() => true,
+            Some('.') => digits.chars().nth(1).is_some_and(|ch| ch.is_ascii_digit()),
+            _ => false,
+        };
+        if starts_like_number && name.len() - digits.len() <= 1 {
+            name.parse().ok()
+        } else {
+            None
+        }
+    }
+
     fn read_hashmap(&mut self) -> Result<AstNode, ParsingError> {
         let mut result = HashMap::new();
         while self.peek_token()? != LexToken::CurlyParenClose {
-            let key_token = self.get_token()?;
-
-            let key = match key_token {
-                LexToken::QuotedString(key) => key,
-                tok => return Err(ParsingError::UnexpectedToken(tok)),
-            };
+            // the reader never produces atoms or functions, so every form is a valid key
+            let key = HashMapKey::new(self.read_form(false)?).unwrap();
 
             result.insert(key, self.read_form(false)?);
         }
 
         self.get_token()?; // get the '}' token
 
-        return Ok(AstNode::HashMap(result));
+        return Ok(AstNode::HashMap(result, None));
+    }
+
+    fn read_set(&mut self) -> Result<AstNode, ParsingError> {
+        let elements = self.read_form_list(LexToken::CurlyParenClose)?;
+        // same as hash-map keys, every form from the reader is hashable
+        let set: HashSet<HashMapKey> = elements
+            .into_iter()
+            .map(|element| HashMapKey::new(element).unwrap())
+            .collect();
+        Ok(AstNode::Set(set, None))
     }
 }
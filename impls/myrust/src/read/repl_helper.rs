@@ -0,0 +1,304 @@
+use std::borrow::Cow;
+
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Context,
+    Helper,
+};
+
+use crate::{
+    eval::{EnvironmentEntryValue, SharedEnvironment},
+    functions::Arity,
+};
+
+use super::{AstNode, Symbol};
+
+const STRING_COLOR: &str = "\x1b[32m";
+const KEYWORD_COLOR: &str = "\x1b[35m";
+const NUMBER_COLOR: &str = "\x1b[36m";
+const COMMENT_COLOR: &str = "\x1b[90m";
+const MATCHING_BRACKET_COLOR: &str = "\x1b[1;34m";
+const HINT_COLOR: &str = "\x1b[90m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+// completion, highlighting and hints for the repl, using the definitions of the running program
+pub struct ReplHelper {
+    environment: SharedEnvironment,
+}
+
+impl ReplHelper {
+    pub fn new(environment: SharedEnvironment) -> Self {
+        Self { environment }
+    }
+}
+
+// how a call to the function named `name` looks like, e.g. "(nth _ _)" or "(f x & more)"
+pub fn call_signature(environment: &SharedEnvironment, name: &str) -> Option<String> {
+    let entry = environment.borrow().find(Symbol::find(name)?)?;
+    // a variable can hold a native function, like after (def! plus +)
+    let value = match entry.value() {
+        EnvironmentEntryValue::Value(AstNode::FunctionPtr(func, _)) => func.value(),
+        value => value,
+    };
+    let params = match value {
+        EnvironmentEntryValue::NativeFunction(func) => arity_params(func.arity()),
+        EnvironmentEntryValue::Value(AstNode::Lambda(lambda, _)) => lambda
+            .params
+            .iter()
+            .map(|param| param.name().to_string())
+            .collect(),
+        _ => return None,
+    };
+
+    let mut signature = format!("({}", name);
+    for param in params {
+        signature.push(' ');
+        signature.push_str(&param);
+    }
+    signature.push(')');
+    Some(signature)
+}
+
+fn arity_params(arity: Arity) -> Vec<String> {
+    let mut params = vec!["_".to_string(); arity.min];
+    match arity.max {
+        Some(max) => params.extend((arity.min..max).map(|_| "[_]".to_string())),
+        None => params.extend(["&".to_string(), "more".to_string()]),
+    }
+    params
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    // the names defined in the environment that start like the symbol before the cursor
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|ch: char| ends_name(ch) || "'`~@^".contains(ch))
+            .map_or(0, |index| index + 1);
+        let prefix = &line[start..pos];
+        if prefix.is_empty() || prefix.starts_with(['"', ';', ':']) {
+            return Ok((pos, vec![]));
+        }
+
+        let mut candidates: Vec<String> = self
+            .environment
+            .borrow()
+            .get_keys()
+            .into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+
+    // the signature of the function called by the innermost unclosed list, at the end of the line
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        if pos < line.len() {
+            return None;
+        }
+        let tokens = scan(line);
+        let mut open_lists = vec![];
+        for (index, token) in tokens.iter().enumerate() {
+            match token.kind {
+                TokenKind::Open => open_lists.push(index),
+                TokenKind::Close => {
+                    open_lists.pop();
+                }
+                _ => {}
+            }
+        }
+
+        let open = *open_lists.last()?;
+        if &line[tokens[open].start..tokens[open].end] != "(" {
+            return None;
+        }
+        let head = tokens
+            .get(open + 1)
+            .filter(|head| head.kind == TokenKind::Name)?;
+        let signature = call_signature(&self.environment, &line[head.start..head.end])?;
+        Some(format!("  ; {}", signature))
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let tokens = scan(line);
+        let matching = matching_brackets(&tokens, pos);
+
+        let mut highlighted = String::with_capacity(line.len() * 2);
+        let mut copied = 0;
+        for (index, token) in tokens.iter().enumerate() {
+            let color = match token.kind {
+                TokenKind::String { .. } => STRING_COLOR,
+                TokenKind::Keyword => KEYWORD_COLOR,
+                TokenKind::Number => NUMBER_COLOR,
+                TokenKind::Comment => COMMENT_COLOR,
+                TokenKind::Open | TokenKind::Close if matching.contains(&index) => {
+                    MATCHING_BRACKET_COLOR
+                }
+                _ => continue,
+            };
+            highlighted.push_str(&line[copied..token.start]);
+            highlighted.push_str(color);
+            highlighted.push_str(&line[token.start..token.end]);
+            highlighted.push_str(RESET_COLOR);
+            copied = token.end;
+        }
+        if copied == 0 {
+            return Cow::Borrowed(line);
+        }
+        highlighted.push_str(&line[copied..]);
+        Cow::Owned(highlighted)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", HINT_COLOR, hint, RESET_COLOR))
+    }
+
+    // the matching bracket changes when the cursor moves
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TokenKind {
+    Open,  // ( [ { #{
+    Close, // ) ] }
+    String { closed: bool },
+    Comment,
+    Keyword,
+    Number,
+    Name,
+    Quote, // ' ` ~ ~@ @ ^
+}
+
+// a token of a line, as byte offsets
+struct Token {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+}
+
+// characters that end a name or keyword, like in the lexer
+fn ends_name(ch: char) -> bool {
+    ch.is_whitespace() || "()[]{},".contains(ch)
+}
+
+// splits the source in tokens like the lexer, but never fails: an unclosed string goes
+// until the end. whitespace is skipped.
+fn scan(source: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        let kind = match ch {
+            _ if ch.is_whitespace() || ch == ',' => continue,
+            '(' | '[' | '{' => TokenKind::Open,
+            '#' if chars.next_if(|&(_, next)| next == '{').is_some() => TokenKind::Open,
+            ')' | ']' | '}' => TokenKind::Close,
+            '\'' | '`' | '@' | '^' => TokenKind::Quote,
+            '~' => {
+                chars.next_if(|&(_, next)| next == '@');
+                TokenKind::Quote
+            }
+            '"' => {
+                let mut closed = false;
+                while let Some((_, ch)) = chars.next() {
+                    match ch {
+                        '"' => {
+                            closed = true;
+                            break;
+                        }
+                        '\\' => {
+                            chars.next();
+                        }
+                        _ => {}
+                    }
+                }
+                TokenKind::String { closed }
+            }
+            ';' => {
+                while chars.next_if(|&(_, next)| next != '\n').is_some() {}
+                TokenKind::Comment
+            }
+            _ => {
+                while chars.next_if(|&(_, next)| !ends_name(next)).is_some() {}
+                TokenKind::Name
+            }
+        };
+        let end = chars.peek().map_or(source.len(), |&(end, _)| end);
+        let kind = match kind {
+            TokenKind::Name if ch == ':' => TokenKind::Keyword,
+            TokenKind::Name if is_number(&source[start..end]) => TokenKind::Number,
+            kind => kind,
+        };
+        tokens.push(Token { kind, start, end });
+    }
+    tokens
+}
+
+// numbers start with a digit, after an optional sign or dot, like in the parser
+fn is_number(name: &str) -> bool {
+    let digits = name.strip_prefix(['+', '-']).unwrap_or(name);
+    let digits = digits.strip_prefix('.').unwrap_or(digits);
+    digits.starts_with(|ch: char| ch.is_ascii_digit())
+}
+
+// the indexes of the bracket at the cursor (or just before it) and of its matching bracket
+fn matching_brackets(tokens: &[Token], pos: usize) -> Vec<usize> {
+    let at_cursor = tokens
+        .iter()
+        .position(|token| token.start == pos)
+        .filter(|&index| matches!(tokens[index].kind, TokenKind::Open | TokenKind::Close))
+        .or_else(|| {
+            tokens
+                .iter()
+                .position(|token| token.end == pos)
+                .filter(|&index| matches!(tokens[index].kind, TokenKind::Open | TokenKind::Close))
+        });
+    let Some(at_cursor) = at_cursor else {
+        return vec![];
+    };
+
+    let mut open_brackets = vec![];
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::Open => open_brackets.push(index),
+            TokenKind::Close => match open_brackets.pop() {
+                Some(open) if open == at_cursor || index == at_cursor => return vec![open, index],
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    vec![]
+}
+
+// whether the source ends inside a string or an open list, vector or map.
+// extra closing brackets don't make it incomplete, the parser reports them.
+pub(super) fn is_incomplete(source: &str) -> bool {
+    let mut depth = 0;
+    for token in scan(source) {
+        match token.kind {
+            TokenKind::Open => depth += 1,
+            TokenKind::Close => depth -= 1,
+            TokenKind::String { closed: false } => return true,
+            _ => {}
+        }
+    }
+    depth > 0
+}
@@ -0,0 +1,252 @@
+use std::cmp::Ordering;
+
+use im_rc::{vector, OrdMap, Vector};
+
+use crate::eval::EvalError;
+
+use super::{AstNode, BigInt, HashMapKey};
+
+// calls the lisp comparator (first parameter) on two keys.
+// provided by the caller, as evaluating code needs the evaluator and an environment.
+pub type ComparatorCall<'a> =
+    dyn FnMut(&AstNode, &AstNode, &AstNode) -> Result<Ordering, EvalError> + 'a;
+
+// the backing store of sorted-map (Sorted<AstNode>) and sorted-set (Sorted<()>).
+#[derive(Clone)]
+pub enum Sorted<V> {
+    Natural(OrdMap<SortKey, V>),
+    // a lisp comparator can't be used to implement Ord, as it needs the evaluator and it can fail.
+    // the entries are kept sorted in a vector instead, and located with a binary search.
+    Custom(Box<AstNode>, Vector<(HashMapKey, V)>),
+}
+
+impl<V: Clone> Sorted<V> {
+    pub fn natural() -> Self {
+        Sorted::Natural(OrdMap::new())
+    }
+
+    pub fn by(comparator: AstNode) -> Self {
+        Sorted::Custom(Box::new(comparator), Vector::new())
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Sorted::Natural(tree) => tree.len(),
+            Sorted::Custom(_, entries) => entries.len(),
+        }
+    }
+
+    // entries in key order
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&HashMapKey, &V)> + '_> {
+        match self {
+            Sorted::Natural(tree) => Box::new(tree.iter().map(|(key, value)| (&key.0, value))),
+            Sorted::Custom(_, entries) => Box::new(entries.iter().map(|(key, value)| (key, value))),
+        }
+    }
+
+    pub fn into_entries(self) -> Vector<(HashMapKey, V)> {
+        match self {
+            Sorted::Natural(tree) => tree
+                .into_iter()
+                .map(|(key, value)| (key.0, value))
+                .collect(),
+            Sorted::Custom(_, entries) => entries,
+        }
+    }
+
+    pub fn get(
+        &self,
+        key: &HashMapKey,
+        call: &mut ComparatorCall,
+    ) -> Result<Option<&V>, EvalError> {
+        match self {
+            Sorted::Natural(tree) => Ok(tree.get(&SortKey(key.clone()))),
+            Sorted::Custom(comparator, entries) => {
+                Ok(match Self::search(comparator, entries, key, call)? {
+                    Ok(index) => Some(&entries[index].1),
+                    Err(_) => None,
+                })
+            }
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        key: HashMapKey,
+        value: V,
+        call: &mut ComparatorCall,
+    ) -> Result<(), EvalError> {
+        match self {
+            Sorted::Natural(tree) => {
+                tree.insert(SortKey(key), value);
+            }
+            Sorted::Custom(comparator, entries) => {
+                match Self::search(comparator, entries, &key, call)? {
+                    Ok(index) => {
+                        entries.set(index, (key, value));
+                    }
+                    Err(index) => entries.insert(index, (key, value)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &HashMapKey, call: &mut ComparatorCall) -> Result<(), EvalError> {
+        match self {
+            Sorted::Natural(tree) => {
+                tree.remove(&SortKey(key.clone()));
+            }
+            Sorted::Custom(comparator, entries) => {
+                if let Ok(index) = Self::search(comparator, entries, key, call)? {
+                    entries.remove(index);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // same as slice::binary_search_by, but the comparator can fail
+    fn search(
+        comparator: &AstNode,
+        entries: &Vector<(HashMapKey, V)>,
+        key: &HashMapKey,
+        call: &mut ComparatorCall,
+    ) -> Result<Result<usize, usize>, EvalError> {
+        let key = key.to_ast_node();
+        let (mut low, mut high) = (0, entries.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            match call(comparator, &entries[middle].0.to_ast_node(), &key)? {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Ok(Ok(middle)),
+            }
+        }
+        Ok(Err(low))
+    }
+}
+
+// a key of a sorted collection without a custom comparator, ordered by natural_cmp
+#[derive(Clone, Debug)]
+pub struct SortKey(HashMapKey);
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        natural_cmp(&self.0.to_ast_node(), &other.0.to_ast_node())
+    }
+}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SortKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortKey {}
+
+// total order between hashable values: numbers by value, strings, keywords and symbols alphabetically,
+// sequences element by element. values of different kinds are ordered by kind, nil first.
+fn natural_cmp(a: &AstNode, b: &AstNode) -> Ordering {
+    match (a, b) {
+        (AstNode::Int(a), AstNode::Int(b)) => a.cmp(b),
+        (AstNode::Float(a), AstNode::Float(b)) => a.total_cmp(b),
+        (AstNode::Float(a), b) if kind_rank(b) == 2 => a.total_cmp(&to_float(b)),
+        (a, AstNode::Float(b)) if kind_rank(a) == 2 => to_float(a).total_cmp(b),
+        (AstNode::Int(_) | AstNode::BigInt(_), AstNode::Int(_) | AstNode::BigInt(_)) => {
+            to_bigint(a).cmp(&to_bigint(b))
+        }
+        (AstNode::Bool(a), AstNode::Bool(b)) => a.cmp(b),
+        (AstNode::String(a), AstNode::String(b)) => a.cmp(b),
+        (AstNode::Keyword(a), AstNode::Keyword(b)) => a.cmp(b),
+        (AstNode::UnresolvedSymbol(a, _), AstNode::UnresolvedSymbol(b, _)) => {
+            a.name().cmp(b.name())
+        }
+        (
+            AstNode::List(a, _, _) | AstNode::Vector(a, _),
+            AstNode::List(b, _, _) | AstNode::Vector(b, _),
+        ) => a
+            .len()
+            .cmp(&b.len())
+            .then_with(|| cmp_sequences(a.iter(), b.iter())),
+        // unordered collections are compared as their sorted entries
+        _ if kind_rank(a) == kind_rank(b) && kind_rank(a) >= 6 => {
+            let (a, b) = (sorted_entries(a), sorted_entries(b));
+            a.len()
+                .cmp(&b.len())
+                .then_with(|| cmp_sequences(a.iter(), b.iter()))
+        }
+        _ => kind_rank(a).cmp(&kind_rank(b)),
+    }
+}
+
+fn kind_rank(node: &AstNode) -> u8 {
+    match node {
+        AstNode::Nil => 0,
+        AstNode::Bool(_) => 1,
+        AstNode::Int(_) | AstNode::BigInt(_) | AstNode::Float(_) => 2,
+        AstNode::String(_) => 3,
+        AstNode::Keyword(_) => 4,
+        AstNode::UnresolvedSymbol(_, _) => 5,
+        AstNode::List(_, _, _) | AstNode::Vector(_, _) => 6,
+        AstNode::Set(_, _) | AstNode::SortedSet(_, _) => 7,
+        AstNode::HashMap(_, _) | AstNode::SortedMap(_, _) => 8,
+        // not hashable, so never used as keys
+        AstNode::Atom(_) | AstNode::FunctionPtr(_, _) | AstNode::Lambda(_, _) => 9,
+    }
+}
+
+fn cmp_sequences<'a>(
+    a: impl Iterator<Item = &'a AstNode>,
+    b: impl Iterator<Item = &'a AstNode>,
+) -> Ordering {
+    for (a, b) in a.zip(b) {
+        match natural_cmp(a, b) {
+            Ordering::Equal => continue,
+            ordering => return ordering,
+        }
+    }
+    Ordering::Equal
+}
+
+// sets as their elements, maps as [key value] vectors
+fn sorted_entries(node: &AstNode) -> Vec<AstNode> {
+    let mut entries: Vec<AstNode> = match node {
+        AstNode::Set(set, _) => set.iter().map(|x| x.to_ast_node()).collect(),
+        AstNode::SortedSet(set, _) => set.iter().map(|x| x.0.to_ast_node()).collect(),
+        AstNode::HashMap(hm, _) => hm
+            .iter()
+            .map(|(k, v)| AstNode::Vector(vector![k.to_ast_node(), v.clone()], None))
+            .collect(),
+        AstNode::SortedMap(map, _) => map
+            .iter()
+            .map(|(k, v)| AstNode::Vector(vector![k.to_ast_node(), v.clone()], None))
+            .collect(),
+        _ => vec![],
+    };
+    entries.sort_by(natural_cmp);
+    entries
+}
+
+fn to_float(node: &AstNode) -> f64 {
+    match node {
+        AstNode::Int(num) => *num as f64,
+        AstNode::BigInt(num) => num.to_f64(),
+        AstNode::Float(num) => *num,
+        _ => unreachable!(),
+    }
+}
+
+fn to_bigint(node: &AstNode) -> BigInt {
+    match node {
+        AstNode::Int(num) => BigInt::from(*num),
+        AstNode::BigInt(num) => num.clone(),
+        _ => unreachable!(),
+    }
+}
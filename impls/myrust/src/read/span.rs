@@ -0,0 +1,24 @@
+use std::{fmt::Display, rc::Rc};
+
+// position in the source code where a token or a form starts.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SourceSpan {
+    pub file: Option<Rc<str>>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SourceSpan {
+    pub fn new(file: Option<Rc<str>>, line: usize, column: usize) -> Self {
+        Self { file, line, column }
+    }
+}
+
+impl Display for SourceSpan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}:{}", file, self.line, self.column),
+            None => write!(f, "<input>:{}:{}", self.line, self.column),
+        }
+    }
+}
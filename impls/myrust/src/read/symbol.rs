@@ -0,0 +1,56 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Display};
+
+// an interned symbol name. comparing or hashing a symbol is comparing or hashing its id.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Symbol(u32);
+
+// names are never freed: a program only uses a bounded number of symbols,
+// unless it creates them from strings at runtime with `symbol`
+#[derive(Default)]
+struct Interner {
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, u32>,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+impl Symbol {
+    pub fn new(name: &str) -> Symbol {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            if let Some(id) = interner.ids.get(name) {
+                return Symbol(*id);
+            }
+            let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+            let id = interner.names.len() as u32;
+            interner.names.push(name);
+            interner.ids.insert(name, id);
+            Symbol(id)
+        })
+    }
+
+    // the symbol with that name if it's already interned, without adding the name
+    pub fn find(name: &str) -> Option<Symbol> {
+        INTERNER.with(|interner| interner.borrow().ids.get(name).map(|id| Symbol(*id)))
+    }
+
+    pub fn name(self) -> &'static str {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize])
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// where a local variable lives: the number of environments to go up, and the position in there.
+// computed ahead of time by the resolver, and checked against the symbol name when used.
+#[derive(Clone, Copy, Debug)]
+pub struct LexicalAddress {
+    pub depth: usize,
+    pub slot: usize,
+}
@@ -0,0 +1,207 @@
+use std::{path::PathBuf, time::Instant};
+
+use crate::{
+    eval::{
+        new_base_environment, Environment, EnvironmentEntryValue, Evaluator, SharedEnvironment,
+        TraceFlag,
+    },
+    load_startup, print_eval_result_error,
+    read::{
+        call_signature, AstNode, AstPrintFormat, AstPrinter, InputError, InputSource,
+        REPLTerminalInputSource, Symbol,
+    },
+    run_source, Limits,
+};
+
+const HELP: &str = "\
+:doc name          how to call a function, and what it is
+:time expr         evaluates expr and shows how long it took
+:env [prefix]      lists the definitions, or the ones starting with prefix
+:load file         evaluates the forms of a file
+:reset             starts again from a fresh environment
+:trace [calls|native-calls] on|off
+                   prints the fn* calls, or the native function calls
+:quit              leaves the repl (like ctrl-d)";
+
+// values longer than this are cut in the :env list
+const MAX_ENV_VALUE_LENGTH: usize = 100;
+
+// an entry starting with one of these names is run by the repl instead of being evaluated.
+// other entries starting with a colon are keywords, like :kw
+enum Command<'a> {
+    Doc(&'a str),
+    Time(&'a str),
+    Env(&'a str),
+    Load(&'a str),
+    Reset,
+    Trace(&'a str),
+    Help,
+    Quit,
+}
+
+impl<'a> Command<'a> {
+    fn parse(entry: &'a str) -> Option<Command<'a>> {
+        let entry = entry.trim();
+        let (name, argument) = match entry.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (entry, ""),
+        };
+        Some(match name {
+            ":doc" => Command::Doc(argument),
+            ":time" => Command::Time(argument),
+            ":env" => Command::Env(argument),
+            ":load" => Command::Load(argument),
+            ":reset" => Command::Reset,
+            ":trace" => Command::Trace(argument),
+            ":help" => Command::Help,
+            ":quit" => Command::Quit,
+            _ => return None,
+        })
+    }
+}
+
+pub fn run_repl(
+    evaluator: &Evaluator,
+    environment: &SharedEnvironment,
+    history_file: Option<PathBuf>,
+    limits: Limits,
+) {
+    let printer = AstPrinter::new(AstPrintFormat::Repr);
+    let mut input = REPLTerminalInputSource::new(environment.clone(), history_file);
+    // where the next entry starts, for the positions in errors
+    let mut line = 1;
+
+    loop {
+        let entry = match input.read() {
+            Ok(entry) => entry,
+            Err(InputError::RetriableError) => continue,
+            Err(_) => return,
+        };
+        let first_line = line;
+        line += entry.matches('\n').count();
+        // each entry gets the whole budget, so that the session goes on after one runs out
+        limits.start(evaluator);
+
+        match Command::parse(&entry) {
+            Some(Command::Quit) => return,
+            Some(command) => run_command(command, first_line, evaluator, environment, &printer),
+            None => run_source(
+                entry,
+                "<repl>",
+                first_line,
+                evaluator,
+                environment,
+                Some(&printer),
+            ),
+        }
+    }
+}
+
+fn run_command(
+    command: Command,
+    first_line: usize,
+    evaluator: &Evaluator,
+    environment: &SharedEnvironment,
+    printer: &AstPrinter,
+) {
+    match command {
+        Command::Doc("") => println!("Usage: :doc name"),
+        Command::Doc(name) => print_doc(name, environment, printer),
+        Command::Time("") => println!("Usage: :time expr"),
+        Command::Time(expr) => {
+            let start = Instant::now();
+            run_source(
+                expr.to_string(),
+                "<repl>",
+                first_line,
+                evaluator,
+                environment,
+                Some(printer),
+            );
+            println!("Elapsed: {:.3} ms", start.elapsed().as_secs_f64() * 1000.0);
+        }
+        Command::Env(prefix) => print_env(prefix, environment, printer),
+        Command::Load("") => println!("Usage: :load file"),
+        Command::Load(path) => match std::fs::read_to_string(path) {
+            Ok(content) => run_source(content, path, 1, evaluator, environment, None),
+            Err(err) => println!("Cannot read '{}': {}", path, err),
+        },
+        Command::Reset => {
+            // the environment is replaced in place, so that the closures and the completion see
+            // the new one. the variables are looked up again by name the next time.
+            environment.replace(Environment::new_child(new_base_environment().get_root()));
+            // like at startup, the startup file isn't limited
+            Limits::default().start(evaluator);
+            if let Err(err) = load_startup(evaluator, environment) {
+                print_eval_result_error(Err(err));
+            }
+        }
+        Command::Trace(argument) => {
+            let words: Vec<&str> = argument.split_whitespace().collect();
+            let setting = match words[..] {
+                [state] | ["calls", state] => Some((TraceFlag::TraceFnCalls, state)),
+                ["native-calls", state] => Some((TraceFlag::TraceNativeFunctionCalls, state)),
+                _ => None,
+            };
+            match setting {
+                Some((flag, "on")) => evaluator.set_trace(flag, true),
+                Some((flag, "off")) => evaluator.set_trace(flag, false),
+                _ => println!("Usage: :trace [calls|native-calls] on|off"),
+            }
+        }
+        Command::Help => println!("{}", HELP),
+        Command::Quit => {}
+    }
+}
+
+fn print_doc(name: &str, environment: &SharedEnvironment, printer: &AstPrinter) {
+    let entry = Symbol::find(name).and_then(|symbol| environment.borrow().find(symbol));
+    let Some(entry) = entry else {
+        println!("'{}' not found", name);
+        return;
+    };
+    let value = match entry.value() {
+        EnvironmentEntryValue::Value(AstNode::FunctionPtr(func, _)) => func.value(),
+        value => value,
+    };
+    let description = match value {
+        EnvironmentEntryValue::NativeFunction(func) if func.evaluates_arguments() => {
+            "native function".to_string()
+        }
+        EnvironmentEntryValue::NativeFunction(_) => "special form".to_string(),
+        EnvironmentEntryValue::Value(AstNode::Lambda(lambda, _)) => format!(
+            "{}: {}",
+            if lambda.is_macro { "macro" } else { "fn*" },
+            printer.ast_to_string(&lambda.body)
+        ),
+        EnvironmentEntryValue::Value(value) => {
+            println!("{} = {}", name, printer.ast_to_string(value));
+            return;
+        }
+    };
+    if let Some(signature) = call_signature(environment, name) {
+        println!("{}", signature);
+    }
+    println!("  {}", description);
+}
+
+fn print_env(prefix: &str, environment: &SharedEnvironment, printer: &AstPrinter) {
+    let mut names = environment.borrow().get_keys();
+    names.retain(|name| name.starts_with(prefix));
+    names.sort();
+    names.dedup();
+
+    for name in names {
+        let entry = Symbol::find(&name).and_then(|symbol| environment.borrow().find(symbol));
+        let Some(entry) = entry else {
+            continue;
+        };
+        let value = printer.ast_to_string(&entry.to_ast_node());
+        if value.chars().count() > MAX_ENV_VALUE_LENGTH {
+            let value: String = value.chars().take(MAX_ENV_VALUE_LENGTH).collect();
+            println!("{} = {}...", name, value);
+        } else {
+            println!("{} = {}", name, value);
+        }
+    }
+}
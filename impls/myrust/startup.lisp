@@ -22,7 +22,7 @@
 (def! first (fn* (list) 
   (if
     (< 0 (count list))
-    (nth list 0)
+    (nth (seq list) 0)
     nil
   )
 ))
@@ -122,14 +122,6 @@
 
 (defmacro! hash-map (fn* (& args) `(assoc {} ~@args ) ))
 
-(def! time-ms (fn* (coll &elements) (throw "not implemented"))) ; get system time in ms
-(def! meta (fn* (node) (throw "not implemented"))) ; returns metadata
-(def! with-meta (fn* (node metadata) (throw "not implemented"))) ; sets metadata
-(def! seq (fn* (node metadata) (throw "not implemented"))) ; sets metadata
-(def! conj (fn* (node metadata) (throw "not implemented"))) ; sets metadata
-
-; string?, number?, seq, and conj
-
 (def! *ARGV* (if (> (count (get-argv)) 1)
   (rest (rest (get-argv)))
   (rest (get-argv)